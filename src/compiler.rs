@@ -0,0 +1,1006 @@
+use std::{fmt, mem, rc::Rc};
+
+use crate::{
+    error::{Error, Location, Result},
+    lexer::Span,
+    parser::{Op, Type, AST},
+};
+
+/// The instructions of the [`Vm`](crate::vm::Vm). Each is a byte followed by
+/// its operands, big-endian `u16`s documented as `operand, ...`. Binary
+/// operators find their left operand on top, as the right one is evaluated
+/// first, and jumps go to an offset in the same chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// `constant`
+    Constant,
+    Nil,
+    True,
+    False,
+    /// The value of statements that have none, like `let`.
+    Idle,
+    Pop,
+    /// `count`: drops what a `break` or `continue` leaves unfinished.
+    PopN,
+    /// `name`
+    GetName,
+    /// `name`: binds the value on top in the current scope. Anonymous
+    /// functions are named after it.
+    DefineName,
+    /// `name`: rebinds a declared name to the value on top.
+    SetName,
+    /// `name, depth, slot`: a local, in a slot of the scope `depth` scopes
+    /// out from the current one.
+    GetLocal,
+    /// `name, slot`: binds the value on top in a slot of the current scope,
+    /// naming anonymous functions like [`OpCode::DefineName`].
+    DefineLocal,
+    /// `name, depth, slot`: rebinds a declared local to the value on top.
+    SetLocal,
+    /// `doc`: the doc comment of the function on top, unless it has one.
+    Document,
+    /// `name`: the field `name` of the value on top.
+    GetMember,
+    /// `op`: pops a container, a key and the value to store there,
+    /// combined with the current one by the assignment operator `op`.
+    SetIndex,
+    /// `target, op`: the left side of an assignment isn't a place.
+    NotAssignable,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    FloorDiv,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Index,
+    Range,
+    RangeInclusive,
+    Negate,
+    Not,
+    /// `count`: the top `count` values as an array.
+    Array,
+    /// `count`: the top `count` key-value pairs as a map.
+    Map,
+    /// Fails unless the value on top can be a map key.
+    CheckKey,
+    /// `count`: the top `count` values joined in a string.
+    Template,
+    /// `target`
+    Jump,
+    /// `target`: pops the condition.
+    JumpIfFalse,
+    /// `target`: for `and`, the value on top stays when jumping.
+    JumpIfFalseOrPop,
+    /// `target`: for `or`, the value on top stays when jumping.
+    JumpIfTrueOrPop,
+    /// Pops the value a `for` loop iterates.
+    IterStart,
+    /// `exit, indexed`: opens a scope with the loop variables bound to the
+    /// next element, or jumps to `exit` when there's none. `indexed` is 1
+    /// when the loop has an index variable besides the item.
+    IterNext,
+    /// Closes the scope of a `for` iteration.
+    PopScope,
+    /// Drops the iterator of the innermost `for` loop.
+    IterEnd,
+    /// `function`: the function constant closed over the current scope.
+    Closure,
+    /// `argc`: calls the value below the arguments.
+    Call,
+    /// `name, argc`: calls a method of the value below the arguments.
+    CallMethod,
+    Return,
+    /// `signal`: a `break`, or a `continue` when `signal` is 1, outside of
+    /// any loop. Returns and fails in the caller.
+    Escape,
+    /// Finishes the program with the value on top.
+    End,
+}
+
+/// Every opcode, at the position of its byte.
+const OPCODES: [OpCode; OpCode::End as usize + 1] = [
+    OpCode::Constant,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Idle,
+    OpCode::Pop,
+    OpCode::PopN,
+    OpCode::GetName,
+    OpCode::DefineName,
+    OpCode::SetName,
+    OpCode::GetLocal,
+    OpCode::DefineLocal,
+    OpCode::SetLocal,
+    OpCode::Document,
+    OpCode::GetMember,
+    OpCode::SetIndex,
+    OpCode::NotAssignable,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Modulo,
+    OpCode::Power,
+    OpCode::FloorDiv,
+    OpCode::Equal,
+    OpCode::NotEqual,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Index,
+    OpCode::Range,
+    OpCode::RangeInclusive,
+    OpCode::Negate,
+    OpCode::Not,
+    OpCode::Array,
+    OpCode::Map,
+    OpCode::CheckKey,
+    OpCode::Template,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::JumpIfFalseOrPop,
+    OpCode::JumpIfTrueOrPop,
+    OpCode::IterStart,
+    OpCode::IterNext,
+    OpCode::PopScope,
+    OpCode::IterEnd,
+    OpCode::Closure,
+    OpCode::Call,
+    OpCode::CallMethod,
+    OpCode::Return,
+    OpCode::Escape,
+    OpCode::End,
+];
+
+/// The assignment operators, numbered as the operand of [`OpCode::SetIndex`]
+/// and [`OpCode::NotAssignable`].
+pub const ASSIGN_OPS: [Op; 5] = [
+    Op::ReAssign,
+    Op::PlusAssign,
+    Op::MinusAssign,
+    Op::StarAssign,
+    Op::SlashAssign,
+];
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        if byte > OpCode::End as u8 {
+            return None;
+        }
+        return Some(OPCODES[byte as usize]);
+    }
+
+    /// How many `u16` operands follow the opcode.
+    pub fn operands(self) -> usize {
+        match self {
+            OpCode::GetLocal | OpCode::SetLocal => 3,
+            OpCode::DefineLocal | OpCode::NotAssignable | OpCode::CallMethod | OpCode::IterNext => {
+                2
+            }
+            OpCode::Constant
+            | OpCode::PopN
+            | OpCode::GetName
+            | OpCode::DefineName
+            | OpCode::SetName
+            | OpCode::Document
+            | OpCode::GetMember
+            | OpCode::SetIndex
+            | OpCode::Array
+            | OpCode::Map
+            | OpCode::Template
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfFalseOrPop
+            | OpCode::JumpIfTrueOrPop
+            | OpCode::Closure
+            | OpCode::Call
+            | OpCode::Escape => 1,
+            _ => 0,
+        }
+    }
+
+    /// How the stack grows when the instruction runs and doesn't jump. The
+    /// ones that never fall through count as leaving a value, like the node
+    /// they were compiled from.
+    fn stack_effect(self, operands: &[u16]) -> isize {
+        let count = operands.first().map_or(0, |&count| count as isize);
+        match self {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Idle
+            | OpCode::GetName
+            | OpCode::GetLocal
+            | OpCode::Closure
+            | OpCode::Escape => 1,
+            OpCode::PopN => -count,
+            OpCode::SetIndex => -2,
+            OpCode::Array | OpCode::Template => 1 - count,
+            OpCode::Map => 1 - 2 * count,
+            OpCode::Call => -count,
+            OpCode::CallMethod => -(operands[1] as isize),
+            OpCode::Pop
+            | OpCode::DefineName
+            | OpCode::DefineLocal
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfFalseOrPop
+            | OpCode::JumpIfTrueOrPop
+            | OpCode::IterStart => -1,
+            op if binary_op(op).is_some() => -1,
+            _ => 0,
+        }
+    }
+}
+
+/// The operator a binary opcode applies.
+pub fn binary_op(op: OpCode) -> Option<Op> {
+    let op = match op {
+        OpCode::Add => Op::Plus,
+        OpCode::Subtract => Op::Minus,
+        OpCode::Multiply => Op::Star,
+        OpCode::Divide => Op::Slash,
+        OpCode::Modulo => Op::Modulo,
+        OpCode::Power => Op::Power,
+        OpCode::FloorDiv => Op::FloorDiv,
+        OpCode::Equal => Op::AssignEqual,
+        OpCode::NotEqual => Op::BangEqual,
+        OpCode::Greater => Op::Greater,
+        OpCode::GreaterEqual => Op::GreaterEqual,
+        OpCode::Less => Op::Less,
+        OpCode::LessEqual => Op::LessEqual,
+        OpCode::Index => Op::Index,
+        OpCode::Range => Op::Range,
+        OpCode::RangeInclusive => Op::RangeInclusive,
+        _ => return None,
+    };
+
+    return Some(op);
+}
+
+fn binary_opcode(op: Op) -> Option<OpCode> {
+    let opcode = match op {
+        Op::Plus | Op::PlusAssign => OpCode::Add,
+        Op::Minus | Op::MinusAssign => OpCode::Subtract,
+        Op::Star | Op::StarAssign => OpCode::Multiply,
+        Op::Slash | Op::SlashAssign => OpCode::Divide,
+        Op::Modulo => OpCode::Modulo,
+        Op::Power => OpCode::Power,
+        Op::FloorDiv => OpCode::FloorDiv,
+        Op::AssignEqual => OpCode::Equal,
+        Op::BangEqual => OpCode::NotEqual,
+        Op::Greater => OpCode::Greater,
+        Op::GreaterEqual => OpCode::GreaterEqual,
+        Op::Less => OpCode::Less,
+        Op::LessEqual => OpCode::LessEqual,
+        Op::Index => OpCode::Index,
+        Op::Range => OpCode::Range,
+        Op::RangeInclusive => OpCode::RangeInclusive,
+        _ => return None,
+    };
+
+    return Some(opcode);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    String(Rc<str>),
+    Function(Rc<Function>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(num) => write!(f, "{num}"),
+            Constant::Float(num) => write!(f, "{num:?}"),
+            Constant::String(str) => write!(f, "{str:?}"),
+            Constant::Function(function) => match &function.name {
+                Some(name) => write!(f, "<fn {name}>"),
+                None => write!(f, "<fn>"),
+            },
+        }
+    }
+}
+
+/// Bytecode and the constants it refers to by index.
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    /// The span of the node each byte of `code` was compiled from, where
+    /// its errors point to.
+    pub spans: Vec<Span>,
+}
+
+impl Chunk {
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        return u16::from_be_bytes([self.code[offset], self.code[offset + 1]]);
+    }
+}
+
+/// Disassembles the chunk, one instruction per line.
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let Some(op) = OpCode::from_byte(self.code[offset]) else {
+                writeln!(f, "{offset:04} <invalid {}>", self.code[offset])?;
+                offset += 1;
+                continue;
+            };
+
+            write!(f, "{offset:04} {op:?}")?;
+            for i in 0..op.operands() {
+                write!(f, " {}", self.read_u16(offset + 1 + 2 * i))?;
+            }
+            let refers_to_constant = matches!(
+                op,
+                OpCode::Constant
+                    | OpCode::GetName
+                    | OpCode::DefineName
+                    | OpCode::SetName
+                    | OpCode::GetLocal
+                    | OpCode::DefineLocal
+                    | OpCode::SetLocal
+                    | OpCode::Document
+                    | OpCode::GetMember
+                    | OpCode::NotAssignable
+                    | OpCode::Closure
+                    | OpCode::CallMethod
+            );
+            if refers_to_constant {
+                let constant = self.read_u16(offset + 1) as usize;
+                write!(f, " ({})", self.constants[constant])?;
+            }
+            writeln!(f)?;
+
+            offset += 1 + 2 * op.operands();
+        }
+
+        Ok(())
+    }
+}
+
+/// A compiled function, or a whole program compiled as one without params.
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: Option<Rc<str>>,
+    pub params: Rc<[Rc<str>]>,
+    pub doc: Option<Rc<str>>,
+    pub chunk: Chunk,
+}
+
+/// Lowers parsed programs to bytecode, one chunk per function. Every node
+/// leaves exactly one value on the stack, so how deep it is at any point is
+/// known while compiling.
+pub struct Compiler {
+    chunk: Chunk,
+    /// The node being compiled, which the code it emits points to.
+    span: Span,
+    /// The values on the stack at the end of the code emitted so far.
+    depth: isize,
+    /// The loops around the node being compiled, innermost last.
+    loops: Vec<Loop>,
+}
+
+struct Loop {
+    /// Where `continue` jumps to.
+    start: usize,
+    /// The `break` jumps to patch once the end of the loop is known.
+    breaks: Vec<usize>,
+    /// The stack depth each iteration starts at.
+    depth: isize,
+    /// `for` loops open a scope per iteration, which `break` and `continue`
+    /// have to close.
+    scoped: bool,
+}
+
+impl Compiler {
+    fn new(span: Span) -> Self {
+        return Self {
+            chunk: Chunk::default(),
+            span,
+            depth: 0,
+            loops: Vec::new(),
+        };
+    }
+
+    /// Compiles a program, which runs like a function that ends with the
+    /// value of its last statement.
+    pub fn compile(program: &[AST]) -> Result<Function> {
+        let mut compiler = Compiler::new(Span::default());
+        if program.is_empty() {
+            compiler.emit(OpCode::Idle, &[]);
+        } else {
+            compiler.compile_statements(program)?;
+        }
+        compiler.emit(OpCode::End, &[]);
+
+        return Ok(Function {
+            name: None,
+            params: Rc::new([]),
+            doc: None,
+            chunk: compiler.chunk,
+        });
+    }
+
+    fn compile_function(
+        &self,
+        name: Option<Rc<str>>,
+        params: Rc<[Rc<str>]>,
+        body: &[AST],
+        doc: Option<Rc<str>>,
+    ) -> Result<Rc<Function>> {
+        let mut compiler = Compiler::new(self.span);
+        compiler.compile_block(body)?;
+        compiler.emit(OpCode::Return, &[]);
+
+        return Ok(Rc::new(Function {
+            name,
+            params,
+            doc,
+            chunk: compiler.chunk,
+        }));
+    }
+
+    /// Compiles a node, and tells errors that don't know where they happened
+    /// yet that it was here.
+    fn compile_ast(&mut self, tree: &AST) -> Result<()> {
+        let outer = mem::replace(&mut self.span, tree.span());
+        let result = self.compile_node(tree).map_err(|err| match err.location() {
+            Location::Unknown => err.with_location(Location::Span(self.span)),
+            _ => err,
+        });
+        self.span = outer;
+
+        return result;
+    }
+
+    fn compile_node(&mut self, tree: &AST) -> Result<()> {
+        match tree {
+            AST::Type(value, _) => self.compile_type(value)?,
+            AST::Local { .. } => {
+                let (get, _, operands) = self.variable(tree)?;
+                self.emit(get, &operands);
+            }
+            AST::If {
+                condition, yes, no, ..
+            } => {
+                self.compile_ast(condition)?;
+                let otherwise = self.emit_jump(OpCode::JumpIfFalse);
+                let depth = self.depth;
+                self.compile_block(yes)?;
+                let end = self.emit_jump(OpCode::Jump);
+
+                self.patch_jump(otherwise)?;
+                self.depth = depth;
+                match no {
+                    Some(no) => self.compile_block(no)?,
+                    None => {
+                        self.emit(OpCode::Nil, &[]);
+                    }
+                }
+                self.patch_jump(end)?;
+            }
+            AST::While {
+                condition, body, ..
+            } => {
+                let start = self.chunk.code.len();
+                self.compile_ast(condition)?;
+                let exit = self.emit_jump(OpCode::JumpIfFalse);
+                let breaks = self.compile_loop(start, false, body)?;
+
+                self.patch_jump(exit)?;
+                for jump in breaks {
+                    self.patch_jump(jump)?;
+                }
+                self.emit(OpCode::Idle, &[]);
+            }
+            AST::For {
+                index,
+                iterable,
+                body,
+                ..
+            } => {
+                self.compile_ast(iterable)?;
+                self.emit(OpCode::IterStart, &[]);
+
+                let start = self.chunk.code.len();
+                let next = self.emit(OpCode::IterNext, &[0, index.is_some() as u16]);
+                let breaks = self.compile_loop(start, true, body)?;
+
+                self.patch_jump(next + 1)?;
+                for jump in breaks {
+                    self.patch_jump(jump)?;
+                }
+                self.emit(OpCode::IterEnd, &[]);
+                self.emit(OpCode::Idle, &[]);
+            }
+            AST::Break(_) | AST::Continue(_) => {
+                let signal = matches!(tree, AST::Continue(_)) as u16;
+                let Some(current) = self.loops.last() else {
+                    self.emit(OpCode::Escape, &[signal]);
+                    return Ok(());
+                };
+
+                let (start, depth, scoped) = (current.start, self.depth, current.scoped);
+                let unfinished = depth - current.depth;
+                if unfinished > 0 {
+                    self.emit(OpCode::PopN, &[unfinished as u16]);
+                }
+                if scoped {
+                    self.emit(OpCode::PopScope, &[]);
+                }
+                if signal == 0 {
+                    let jump = self.emit_jump(OpCode::Jump);
+                    self.loops.last_mut().unwrap().breaks.push(jump);
+                } else {
+                    let start = self.offset(start)?;
+                    self.emit(OpCode::Jump, &[start]);
+                }
+                // it never falls through, but counts as a value like any node
+                self.depth = depth + 1;
+            }
+            AST::Return { value, .. } => {
+                self.compile_ast(value)?;
+                self.emit(OpCode::Return, &[]);
+            }
+
+            AST::Let {
+                ident,
+                value,
+                doc,
+                slot,
+                ..
+            } => {
+                self.compile_ast(value)?;
+                if let Some(doc) = doc {
+                    let doc = self.constant(Constant::String(doc.clone()))?;
+                    self.emit(OpCode::Document, &[doc]);
+                }
+                self.compile_define(ident, *slot)?;
+                self.emit(OpCode::Idle, &[]);
+            }
+
+            AST::Fn {
+                name,
+                params,
+                body,
+                doc,
+                slot,
+                ..
+            } => {
+                let function =
+                    self.compile_function(name.clone(), params.clone(), body, doc.clone())?;
+                let function = self.constant(Constant::Function(function))?;
+                self.emit(OpCode::Closure, &[function]);
+
+                if let Some(name) = name {
+                    self.compile_define(name, *slot)?;
+                    self.emit(OpCode::Idle, &[]);
+                }
+            }
+
+            AST::Call { calle, args, .. } => {
+                if let AST::Expr(Op::Dot, operands, _) = calle.as_ref() {
+                    if let [receiver, name] = operands.as_slice() {
+                        let name = self.constant(Constant::String(member_name(name)))?;
+                        self.compile_ast(receiver)?;
+                        let argc = self.compile_expressions(args)?;
+                        self.emit(OpCode::CallMethod, &[name, argc]);
+                        return Ok(());
+                    }
+                }
+
+                self.compile_ast(calle)?;
+                let argc = self.compile_expressions(args)?;
+                self.emit(OpCode::Call, &[argc]);
+            }
+
+            AST::Expr(op, operands, _) => match (op, operands.as_slice()) {
+                (Op::And | Op::Or, [left, right]) => {
+                    self.compile_ast(left)?;
+                    let decided = if *op == Op::And {
+                        self.emit_jump(OpCode::JumpIfFalseOrPop)
+                    } else {
+                        self.emit_jump(OpCode::JumpIfTrueOrPop)
+                    };
+                    self.compile_ast(right)?;
+                    self.patch_jump(decided)?;
+                }
+                (
+                    Op::ReAssign
+                    | Op::PlusAssign
+                    | Op::MinusAssign
+                    | Op::StarAssign
+                    | Op::SlashAssign,
+                    [target, value],
+                ) => self.compile_reassign(*op, target, value)?,
+                (Op::Dot, [receiver, name]) => {
+                    let name = self.constant(Constant::String(member_name(name)))?;
+                    self.compile_ast(receiver)?;
+                    self.emit(OpCode::GetMember, &[name]);
+                }
+                (op, [left, right]) => {
+                    let Some(opcode) = binary_opcode(*op) else {
+                        return Err(Error::runtime(op, format!("unknown operator in {op}")));
+                    };
+                    self.compile_ast(right)?;
+                    self.compile_ast(left)?;
+                    self.emit(opcode, &[]);
+                }
+                (Op::Grouped | Op::Assing | Op::Fn, [operand]) => self.compile_ast(operand)?,
+                (Op::Minus, [operand]) => {
+                    self.compile_ast(operand)?;
+                    self.emit(OpCode::Negate, &[]);
+                }
+                (Op::Bang, [operand]) => {
+                    self.compile_ast(operand)?;
+                    self.emit(OpCode::Not, &[]);
+                }
+                (op, _) => return Err(Error::runtime(op, format!("unknown operator in {op}"))),
+            },
+        }
+
+        return Ok(());
+    }
+
+    /// A loop body run from `start`, returning its `break` jumps.
+    fn compile_loop(&mut self, start: usize, scoped: bool, body: &[AST]) -> Result<Vec<usize>> {
+        self.loops.push(Loop {
+            start,
+            breaks: Vec::new(),
+            depth: self.depth,
+            scoped,
+        });
+        let compiled = self.compile_block(body);
+        let current = self.loops.pop().unwrap();
+        compiled?;
+
+        self.emit(OpCode::Pop, &[]);
+        if scoped {
+            self.emit(OpCode::PopScope, &[]);
+        }
+        let start = self.offset(start)?;
+        self.emit(OpCode::Jump, &[start]);
+
+        return Ok(current.breaks);
+    }
+
+    /// Leaves the value of the last statement, or `nil` if there's none.
+    fn compile_block(&mut self, block: &[AST]) -> Result<()> {
+        if block.is_empty() {
+            self.emit(OpCode::Nil, &[]);
+            return Ok(());
+        }
+
+        return self.compile_statements(block);
+    }
+
+    fn compile_statements(&mut self, statements: &[AST]) -> Result<()> {
+        for (i, stmt) in statements.iter().enumerate() {
+            if i > 0 {
+                self.emit(OpCode::Pop, &[]);
+            }
+            self.compile_ast(stmt)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Leaves the values one after the other, and returns how many there are.
+    fn compile_expressions(&mut self, expressions: &[AST]) -> Result<u16> {
+        for expression in expressions {
+            self.compile_ast(expression)?;
+        }
+
+        return operand(expressions.len(), "values in one expression");
+    }
+
+    /// Same evaluation order as the tree walker: the value, then the key and
+    /// then the container.
+    fn compile_reassign(&mut self, op: Op, target: &AST, value: &AST) -> Result<()> {
+        self.compile_ast(value)?;
+        let assign = ASSIGN_OPS.iter().position(|&assign| assign == op).unwrap() as u16;
+
+        match target {
+            AST::Type(Type::Ident(_), _) | AST::Local { .. } => {
+                let (get, set, operands) = self.variable(target)?;
+                if let Some(opcode) = binary_opcode(op) {
+                    self.emit(get, &operands);
+                    self.emit(opcode, &[]);
+                }
+                self.emit(set, &operands);
+            }
+            AST::Expr(place @ (Op::Index | Op::Dot), operands, _) if operands.len() == 2 => {
+                if *place == Op::Dot {
+                    let name = self.constant(Constant::String(member_name(&operands[1])))?;
+                    self.emit(OpCode::Constant, &[name]);
+                } else {
+                    self.compile_ast(&operands[1])?;
+                }
+                self.compile_ast(&operands[0])?;
+                self.emit(OpCode::SetIndex, &[assign]);
+            }
+            ast => {
+                let target = self.constant(Constant::String(ast.to_string().into()))?;
+                self.emit(OpCode::NotAssignable, &[target, assign]);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// The opcodes reading and rebinding a variable, and their operands: its
+    /// name, and the depth and slot of a local.
+    fn variable(&mut self, variable: &AST) -> Result<(OpCode, OpCode, Vec<u16>)> {
+        match variable {
+            AST::Local {
+                name, depth, slot, ..
+            } => {
+                let name = self.constant(Constant::String(name.clone()))?;
+                let depth = operand(*depth, "scopes around a variable")?;
+                let slot = operand(*slot, "variables in one scope")?;
+                Ok((OpCode::GetLocal, OpCode::SetLocal, vec![name, depth, slot]))
+            }
+            AST::Type(Type::Ident(name), _) => {
+                let name = self.constant(Constant::String(name.clone()))?;
+                Ok((OpCode::GetName, OpCode::SetName, vec![name]))
+            }
+            ast => unreachable!("only variables are looked up, got {ast}"),
+        }
+    }
+
+    /// Binds the value on top to the slot the resolver gave a declaration, or
+    /// to a global at the top level.
+    fn compile_define(&mut self, name: &Rc<str>, slot: Option<usize>) -> Result<()> {
+        let name = self.constant(Constant::String(name.clone()))?;
+        match slot {
+            Some(slot) => {
+                let slot = operand(slot, "variables in one scope")?;
+                self.emit(OpCode::DefineLocal, &[name, slot]);
+            }
+            None => {
+                self.emit(OpCode::DefineName, &[name]);
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn compile_name(&mut self, name: &Rc<str>) -> Result<()> {
+        let name = self.constant(Constant::String(name.clone()))?;
+        self.emit(OpCode::GetName, &[name]);
+
+        return Ok(());
+    }
+
+    fn compile_type(&mut self, value: &Type) -> Result<()> {
+        match value {
+            Type::Bool(true) => {
+                self.emit(OpCode::True, &[]);
+            }
+            Type::Bool(false) => {
+                self.emit(OpCode::False, &[]);
+            }
+            Type::Nil => {
+                self.emit(OpCode::Nil, &[]);
+            }
+            Type::String(str) => {
+                let str = self.constant(Constant::String(str.clone()))?;
+                self.emit(OpCode::Constant, &[str]);
+            }
+            Type::Int(num) => {
+                let num = self.constant(Constant::Int(*num))?;
+                self.emit(OpCode::Constant, &[num]);
+            }
+            Type::Float(num) => {
+                let num = self.constant(Constant::Float(*num))?;
+                self.emit(OpCode::Constant, &[num]);
+            }
+            Type::Ident(ident) => self.compile_name(ident)?,
+            Type::Arr(items) => {
+                let count = self.compile_expressions(items)?;
+                self.emit(OpCode::Array, &[count]);
+            }
+            Type::Template(parts) => {
+                let count = self.compile_expressions(parts)?;
+                self.emit(OpCode::Template, &[count]);
+            }
+            Type::Map(pairs) => {
+                for (key, value) in pairs.iter() {
+                    self.compile_ast(key)?;
+                    self.emit(OpCode::CheckKey, &[]);
+                    self.compile_ast(value)?;
+                }
+                let count = operand(pairs.len(), "entries in one map")?;
+                self.emit(OpCode::Map, &[count]);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Appends an instruction pointing at the node being compiled, and
+    /// returns its offset.
+    fn emit(&mut self, op: OpCode, operands: &[u16]) -> usize {
+        debug_assert_eq!(op.operands(), operands.len(), "operands of {op:?}");
+        let offset = self.chunk.code.len();
+        self.chunk.code.push(op as u8);
+        for operand in operands {
+            self.chunk.code.extend(operand.to_be_bytes());
+        }
+        self.chunk.spans.resize(self.chunk.code.len(), self.span);
+        self.depth += op.stack_effect(operands);
+
+        return offset;
+    }
+
+    /// Emits a jump to be patched later, and returns the offset of its target.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        return self.emit(op, &[u16::MAX]) + 1;
+    }
+
+    /// Points the jump target at `operand` to the code emitted next.
+    fn patch_jump(&mut self, operand: usize) -> Result<()> {
+        let target = self.offset(self.chunk.code.len())?.to_be_bytes();
+        self.chunk.code[operand..operand + 2].copy_from_slice(&target);
+
+        return Ok(());
+    }
+
+    fn offset(&self, offset: usize) -> Result<u16> {
+        return operand(offset, "instructions in one function");
+    }
+
+    /// Adds a constant to the pool, reusing an equal one.
+    fn constant(&mut self, constant: Constant) -> Result<u16> {
+        let existing = self
+            .chunk
+            .constants
+            .iter()
+            .position(|known| match (known, &constant) {
+                (Constant::Int(l), Constant::Int(r)) => l == r,
+                (Constant::Float(l), Constant::Float(r)) => l.to_bits() == r.to_bits(),
+                (Constant::String(l), Constant::String(r)) => l == r,
+                _ => false,
+            });
+        if let Some(index) = existing {
+            return Ok(index as u16);
+        }
+
+        self.chunk.constants.push(constant);
+        return operand(self.chunk.constants.len() - 1, "constants in one function");
+    }
+}
+
+fn operand(value: usize, what: &str) -> Result<u16> {
+    return u16::try_from(value)
+        .ok()
+        .filter(|&value| value != u16::MAX)
+        .ok_or_else(|| Error::runtime("COMPILE", format!("too many {what}")));
+}
+
+/// The parser stores the name after `.` as a string literal.
+fn member_name(name: &AST) -> Rc<str> {
+    match name {
+        AST::Type(Type::String(name), _) => name.clone(),
+        ast => ast.to_string().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compiler, OpCode, OPCODES};
+    use crate::{
+        error::Result,
+        eval::{Env, Value},
+        parser::Parser,
+        resolver::Resolver,
+    };
+
+    #[test]
+    fn opcodes() {
+        for byte in 0..=OpCode::End as u8 {
+            assert_eq!(byte, OPCODES[byte as usize] as u8);
+            assert_eq!(Some(OPCODES[byte as usize]), OpCode::from_byte(byte));
+        }
+        assert_eq!(None, OpCode::from_byte(OpCode::End as u8 + 1));
+    }
+
+    #[test]
+    fn bytecode() -> Result<()> {
+        let input = [
+            (
+                "let a = 1 + 2; a;",
+                "\
+0000 Constant 0 (2)
+0003 Constant 1 (1)
+0006 Add
+0007 DefineName 2 (\"a\")
+0010 Idle
+0011 Pop
+0012 GetName 2 (\"a\")
+0015 End
+",
+            ),
+            (
+                "while x { if y { break; } }",
+                "\
+0000 GetName 0 (\"x\")
+0003 JumpIfFalse 23
+0006 GetName 1 (\"y\")
+0009 JumpIfFalse 18
+0012 Jump 23
+0015 Jump 19
+0018 Nil
+0019 Pop
+0020 Jump 0
+0023 Idle
+0024 End
+",
+            ),
+            (
+                "for x in xs { f(x, if x { continue; }); x -= 1; }",
+                "\
+0000 GetName 0 (\"xs\")
+0003 IterStart
+0004 IterNext 67 0
+0009 GetName 1 (\"f\")
+0012 GetLocal 2 0 0 (\"x\")
+0019 GetLocal 2 0 0 (\"x\")
+0026 JumpIfFalse 39
+0029 PopN 2
+0032 PopScope
+0033 Jump 4
+0036 Jump 40
+0039 Nil
+0040 Call 2
+0043 Pop
+0044 Constant 3 (1)
+0047 GetLocal 2 0 0 (\"x\")
+0054 Subtract
+0055 SetLocal 2 0 0 (\"x\")
+0062 Pop
+0063 PopScope
+0064 Jump 4
+0067 IterEnd
+0068 Idle
+0069 End
+",
+            ),
+        ];
+
+        // globals the programs use
+        let mut env = Env::new();
+        for name in ["x", "y", "xs", "f"] {
+            env.set(name.into(), Value::Nil);
+        }
+
+        for (code, expected) in input {
+            let program = Parser::new(code.into())
+                .parse()
+                .into_iter()
+                .collect::<Result<Vec<_>>>()?;
+            let program = Resolver::new(&env).resolve(program)?;
+            let function = Compiler::compile(&program)?;
+
+            println!("{}", function.chunk);
+            assert_eq!(expected, function.chunk.to_string());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use std::io::{self, IsTerminal, Write};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    error::{ArithmeticError, Error, Location},
+    lexer::Span,
+};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// An error ready to be shown to a person: a title, the place in the source
+/// it's about with a label under it, and any notes or help that go with it.
+///
+/// ```text
+/// error: reference error
+///  --> main.mk:2:11
+///   |
+/// 2 | print(a + b);
+///   |           ^ 'b' not declared
+///   |
+///   = help: declare it first with `let b = ...;`
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    title: String,
+    label: String,
+    location: Location,
+    notes: Vec<String>,
+    help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(title: impl Into<String>, label: impl Into<String>, location: Location) -> Self {
+        return Self {
+            title: title.into(),
+            label: label.into(),
+            location,
+            notes: Vec::new(),
+            help: Vec::new(),
+        };
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        return self;
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        return self;
+    }
+
+    /// Writes the diagnostic to stderr, in color when it's a terminal.
+    pub fn emit(&self, file: &str, source: &str) {
+        let color = io::stderr().is_terminal();
+        let _ = io::stderr().write_all(self.render(file, source, color).as_bytes());
+    }
+
+    /// Renders the diagnostic against the `source` of `file`. Spans running
+    /// over several lines are underlined up to the end of their first one.
+    pub fn render(&self, file: &str, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+
+        // without a place to point at, the label joins the title
+        let title = match (self.location, self.label.is_empty()) {
+            (Location::Unknown, false) => format!("{}: {}", self.title, self.label),
+            _ => self.title.clone(),
+        };
+        let mut out = format!(
+            "{}{}\n",
+            paint(RED, "error"),
+            paint(BOLD, &format!(": {title}"))
+        );
+
+        let span = match self.location {
+            Location::Span(span) => span,
+            Location::EndOfInput => end_of(source),
+            Location::Unknown => {
+                out.push_str(&format!(" {} {file}\n", paint(BLUE, "-->")));
+                self.render_footer(&mut out, " ", &paint);
+                return out;
+            }
+        };
+
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let offset = span.offset.min(source.len());
+        let line_start = source[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |pos| offset + pos);
+        let line = &source[line_start..line_end];
+
+        // tabs are kept in the padding and wide chars like CJK or emoji take
+        // two columns, so the caret lines up with the source in a terminal
+        let padding: String = source[line_start..offset]
+            .chars()
+            .map(|ch| match ch {
+                '\t' => "\t".to_string(),
+                ch => " ".repeat(ch.width().unwrap_or(0)),
+            })
+            .collect();
+        let underlined: usize = source[offset..(offset + span.len).min(line_end)]
+            .chars()
+            .map(|ch| ch.width().unwrap_or(0))
+            .sum();
+        let carets = "^".repeat(underlined.max(1));
+
+        out.push_str(&format!(
+            "{gutter}{} {file}:{}:{}\n",
+            paint(BLUE, "-->"),
+            span.line,
+            span.column
+        ));
+        out.push_str(&format!("{gutter} {}\n", paint(BLUE, "|")));
+        out.push_str(&format!(
+            "{} {line}\n",
+            paint(BLUE, &format!("{line_number} |"))
+        ));
+        let label = if self.label.is_empty() {
+            carets
+        } else {
+            format!("{carets} {}", self.label)
+        };
+        out.push_str(&format!(
+            "{gutter} {} {padding}{}\n",
+            paint(BLUE, "|"),
+            paint(RED, &label)
+        ));
+
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            out.push_str(&format!("{gutter} {}\n", paint(BLUE, "|")));
+        }
+        self.render_footer(&mut out, &gutter, &paint);
+        return out;
+    }
+
+    fn render_footer(&self, out: &mut String, gutter: &str, paint: &dyn Fn(&str, &str) -> String) {
+        for note in &self.notes {
+            out.push_str(&format!("{gutter} {} {note}\n", paint(BOLD, "= note:")));
+        }
+        for help in &self.help {
+            out.push_str(&format!("{gutter} {} {help}\n", paint(BOLD, "= help:")));
+        }
+    }
+}
+
+/// An empty span right after the last char of `source`, where errors about
+/// a missing token point to.
+fn end_of(source: &str) -> Span {
+    let line_start = source.rfind('\n').map_or(0, |pos| pos + 1);
+    let line = source.matches('\n').count() + 1;
+    let column = source[line_start..].chars().count() + 1;
+
+    return Span::new(source.len(), 0, line, column);
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(err: &Error) -> Self {
+        let location = err.location();
+        let mut diagnostic = match err {
+            Error::Lexical { message, .. } => Diagnostic::new("lexical error", message, location),
+            Error::Syntax { message, .. } => Diagnostic::new("syntax error", message, location),
+            Error::Reference { name, .. } => Diagnostic::new(
+                "reference error",
+                format!("'{name}' not declared"),
+                location,
+            )
+            .with_help(format!("declare it first with `let {name} = ...;`")),
+            Error::Type {
+                operation, message, ..
+            } => Diagnostic::new(format!("type mismatch in '{operation}'"), message, location),
+            Error::Arithmetic {
+                kind, expression, ..
+            } => {
+                let title = match kind {
+                    ArithmeticError::Overflow => "integer overflow",
+                    ArithmeticError::DivisionByZero => "division by zero",
+                };
+                Diagnostic::new(title, expression, location)
+            }
+            Error::Index { index, len, .. } => Diagnostic::new(
+                "index out of bounds",
+                format!("index {index} out of bounds for length {len}"),
+                location,
+            )
+            .with_note("negative indexes count from the end"),
+            Error::Arity {
+                name,
+                expected,
+                got,
+                ..
+            } => Diagnostic::new(
+                format!("wrong number of arguments to '{name}'"),
+                format!("expected {expected} argument(s), got {got}"),
+                location,
+            ),
+            Error::Runtime {
+                operation, message, ..
+            } => Diagnostic::new(format!("runtime error in '{operation}'"), message, location),
+        };
+
+        for frame in err.backtrace() {
+            diagnostic = diagnostic.with_note(frame.to_string());
+        }
+
+        return diagnostic;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+    use crate::{
+        error::{Error, Location},
+        eval::{Env, Evaluator},
+        lexer::{Lexer, Span},
+        parser::Parser,
+    };
+
+    #[test]
+    fn runtime_errors() {
+        let source = "let a = 1;\nprint(a + b);";
+        let err = Evaluator::new(Env::new(), Vec::new())
+            .eval(Parser::new(source.to_string()).parse())
+            .unwrap_err();
+
+        let expected = "\
+error: reference error
+ --> main.mk:2:11
+  |
+2 | print(a + b);
+  |           ^ 'b' not declared
+  |
+  = help: declare it first with `let b = ...;`
+";
+        assert_eq!(
+            expected,
+            Diagnostic::from(&err).render("main.mk", source, false)
+        );
+    }
+
+    #[test]
+    fn lexical_and_syntax_errors() {
+        let source = "let s = \"bad \\q\";";
+        let err = Lexer::new(source.to_string())
+            .find_map(|tok| tok.err())
+            .unwrap();
+
+        let expected = "\
+error: lexical error
+ --> main.mk:1:14
+  |
+1 | let s = \"bad \\q\";
+  |              ^^ Invalid escape sequence '\\q'
+";
+        assert_eq!(
+            expected,
+            Diagnostic::from(&err).render("main.mk", source, false)
+        );
+
+        let source = "let x =\n\t(1 + 2";
+        let err = Parser::new(source.to_string())
+            .parse()
+            .remove(0)
+            .unwrap_err();
+
+        let expected = "\
+error: syntax error
+ --> main.mk:2:8
+  |
+2 | \t(1 + 2
+  | \t      ^ expected RIGHT_PAREN )
+";
+        assert_eq!(
+            expected,
+            Diagnostic::from(&err).render("main.mk", source, false)
+        );
+    }
+
+    #[test]
+    fn wide_chars() {
+        let source = "let 名前 = 😀;";
+        let err = Lexer::new(source.to_string())
+            .find_map(|tok| tok.err())
+            .unwrap();
+
+        let expected = "\
+error: lexical error
+ --> main.mk:1:10
+  |
+1 | let 名前 = 😀;
+  |            ^^ Unexpected character: 😀
+";
+        assert_eq!(
+            expected,
+            Diagnostic::from(&err).render("main.mk", source, false)
+        );
+    }
+
+    #[test]
+    fn notes_help_and_color() {
+        let diagnostic = Diagnostic::new("something broke", "here", Location::Unknown)
+            .with_note("a note")
+            .with_help("some help");
+
+        let expected = "\
+error: something broke: here
+ --> <repl>
+  = note: a note
+  = help: some help
+";
+        assert_eq!(expected, diagnostic.render("<repl>", "", false));
+
+        let source = "1 // 0;";
+        let diagnostic = Diagnostic::from(&Error::Lexical {
+            message: "oops".to_string(),
+            location: Location::Span(Span::new(2, 2, 1, 3)),
+        });
+        let colored = diagnostic.render("main.mk", source, true);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m"));
+        assert!(colored.contains("\x1b[1;31m^^ oops\x1b[0m"));
+    }
+}
@@ -0,0 +1,278 @@
+use core::fmt;
+use std::rc::Rc;
+
+use crate::lexer::Span;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Every failure reported by the lexer, the parser and the evaluator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Source text that can't be split into tokens: stray characters,
+    /// unterminated strings, bad escapes or literals.
+    Lexical { message: String, location: Location },
+    /// Tokens that don't form a valid program.
+    Syntax { message: String, location: Location },
+    /// A name that isn't bound in any scope.
+    Reference {
+        name: Rc<str>,
+        location: Location,
+        backtrace: Vec<Frame>,
+    },
+    /// An operand or argument of the wrong type for `operation`.
+    Type {
+        operation: Rc<str>,
+        message: String,
+        location: Location,
+        backtrace: Vec<Frame>,
+    },
+    /// Integer overflow or a division by zero while computing `expression`.
+    Arithmetic {
+        kind: ArithmeticError,
+        expression: String,
+        location: Location,
+        backtrace: Vec<Frame>,
+    },
+    /// An index outside of a collection of `len` elements.
+    Index {
+        index: i64,
+        len: usize,
+        location: Location,
+        backtrace: Vec<Frame>,
+    },
+    /// A call to `name` with the wrong number of arguments.
+    Arity {
+        name: Rc<str>,
+        expected: usize,
+        got: usize,
+        location: Location,
+        backtrace: Vec<Frame>,
+    },
+    /// Anything else that goes wrong at runtime, like calling a value that
+    /// isn't a function or a `break` outside of a loop.
+    Runtime {
+        operation: Rc<str>,
+        message: String,
+        location: Location,
+        backtrace: Vec<Frame>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow,
+    DivisionByZero,
+}
+
+/// A call to a script function that was running when an error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// `None` for functions that were never bound to a name.
+    pub name: Option<Rc<str>>,
+    /// Where the call was made, `None` for calls made by the host through
+    /// `call_function`.
+    pub call_site: Option<Span>,
+}
+
+/// Where an error happened in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Span(Span),
+    EndOfInput,
+    Unknown,
+}
+
+impl Error {
+    pub fn lexical(message: impl Into<String>, span: Span) -> Self {
+        return Error::Lexical {
+            message: message.into(),
+            location: Location::Span(span),
+        };
+    }
+
+    pub fn syntax(message: impl Into<String>, location: Location) -> Self {
+        return Error::Syntax {
+            message: message.into(),
+            location,
+        };
+    }
+
+    pub fn reference(name: impl Into<Rc<str>>) -> Self {
+        return Error::Reference {
+            name: name.into(),
+            location: Location::Unknown,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub fn type_mismatch(operation: impl fmt::Display, message: impl Into<String>) -> Self {
+        return Error::Type {
+            operation: operation.to_string().into(),
+            message: message.into(),
+            location: Location::Unknown,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub fn arithmetic(kind: ArithmeticError, expression: impl Into<String>) -> Self {
+        return Error::Arithmetic {
+            kind,
+            expression: expression.into(),
+            location: Location::Unknown,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub fn index(index: i64, len: usize) -> Self {
+        return Error::Index {
+            index,
+            len,
+            location: Location::Unknown,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub fn arity(name: impl fmt::Display, expected: usize, got: usize) -> Self {
+        return Error::Arity {
+            name: name.to_string().into(),
+            expected,
+            got,
+            location: Location::Unknown,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub fn runtime(operation: impl fmt::Display, message: impl Into<String>) -> Self {
+        return Error::Runtime {
+            operation: operation.to_string().into(),
+            message: message.into(),
+            location: Location::Unknown,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub fn location(&self) -> Location {
+        match self {
+            Error::Lexical { location, .. }
+            | Error::Syntax { location, .. }
+            | Error::Reference { location, .. }
+            | Error::Type { location, .. }
+            | Error::Arithmetic { location, .. }
+            | Error::Index { location, .. }
+            | Error::Arity { location, .. }
+            | Error::Runtime { location, .. } => *location,
+        }
+    }
+
+    /// The calls that led to the error, innermost first.
+    pub fn backtrace(&self) -> &[Frame] {
+        match self {
+            Error::Lexical { .. } | Error::Syntax { .. } => &[],
+            Error::Reference { backtrace, .. }
+            | Error::Type { backtrace, .. }
+            | Error::Arithmetic { backtrace, .. }
+            | Error::Index { backtrace, .. }
+            | Error::Arity { backtrace, .. }
+            | Error::Runtime { backtrace, .. } => backtrace,
+        }
+    }
+
+    /// Attaches the calls that led to a runtime error, errors found before
+    /// running anything are left as they are.
+    pub fn with_backtrace(mut self, frames: Vec<Frame>) -> Self {
+        match &mut self {
+            Error::Lexical { .. } | Error::Syntax { .. } => {}
+            Error::Reference { backtrace, .. }
+            | Error::Type { backtrace, .. }
+            | Error::Arithmetic { backtrace, .. }
+            | Error::Index { backtrace, .. }
+            | Error::Arity { backtrace, .. }
+            | Error::Runtime { backtrace, .. } => *backtrace = frames,
+        }
+
+        return self;
+    }
+
+    pub fn with_location(mut self, new_location: Location) -> Self {
+        match &mut self {
+            Error::Lexical { location, .. }
+            | Error::Syntax { location, .. }
+            | Error::Reference { location, .. }
+            | Error::Type { location, .. }
+            | Error::Arithmetic { location, .. }
+            | Error::Index { location, .. }
+            | Error::Arity { location, .. }
+            | Error::Runtime { location, .. } => *location = new_location,
+        }
+
+        return self;
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location() {
+            Location::Span(span) => write!(f, "[line: {}, column: {}] ", span.line, span.column)?,
+            Location::EndOfInput => write!(f, "[End of line] ")?,
+            Location::Unknown => {}
+        }
+
+        match self {
+            Error::Lexical { message, .. } | Error::Syntax { message, .. } => {
+                write!(f, "Error: {message}")?
+            }
+            Error::Reference { name, .. } => write!(f, "Reference error: '{name}' not declared")?,
+            Error::Type {
+                operation, message, ..
+            } => write!(f, "[operation: {operation} ] Type mismatch: {message}")?,
+            Error::Arithmetic {
+                kind: ArithmeticError::Overflow,
+                expression,
+                ..
+            } => write!(f, "Overflow error: {expression}")?,
+            Error::Arithmetic {
+                kind: ArithmeticError::DivisionByZero,
+                expression,
+                ..
+            } => write!(f, "Dividing by zero error: {expression}")?,
+            Error::Index { index, len, .. } => write!(
+                f,
+                "[operation: INDEX ] Error: index {index} out of bounds for length {len}"
+            )?,
+            Error::Arity {
+                name,
+                expected,
+                got,
+                ..
+            } => write!(
+                f,
+                "[operation: {name} ] Error: expected {expected} argument(s), got {got}"
+            )?,
+            Error::Runtime {
+                operation, message, ..
+            } => write!(f, "[operation: {operation} ] Error: {message}")?,
+        }
+
+        for frame in self.backtrace() {
+            write!(f, "\n    {frame}")?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "in fn '{name}'")?,
+            None => write!(f, "in anonymous fn")?,
+        }
+
+        match self.call_site {
+            Some(span) => write!(f, " called at line {}", span.line),
+            None => write!(f, " called by the host"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
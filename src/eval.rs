@@ -1,8 +1,28 @@
-use anyhow::{anyhow, Result};
 use core::fmt;
-use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
-
-use crate::parser::{Op, Type, AST};
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, io::Write, rc::Rc};
+
+use crate::{
+    compiler::Function,
+    error::{Error, Frame, Location, Result},
+    lexer::Span,
+    parser::{Op, Type, AST},
+    resolver::Resolver,
+};
+
+mod builtins;
+mod convert;
+pub(crate) mod methods;
+/// Operations on values that don't depend on how a program is run, shared by
+/// the tree walker and the VM so both behave the same.
+pub(crate) mod ops;
+
+pub use builtins::{arity_error, type_error, Builtin, BuiltinFn, NativeFn};
+pub use convert::{FromValue, IntoArgs, IntoValue};
+use methods::eval_member;
+use ops::{
+    assign_index, check_loop_signal, eval_comparison, eval_compound, eval_equality, eval_index,
+    eval_infix_numbers, eval_plus, eval_prefix, eval_range, is_truth, iterate,
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Env {
@@ -23,219 +43,347 @@ impl Env {
             return Ok(value.clone());
         }
         if let Some(env) = &self.outer {
-            return Ok(env.get(name)?);
+            return env.get(name);
+        }
+        if let Some(builtin) = builtins::lookup(name) {
+            return Ok(builtin);
         }
 
-        return Err(anyhow!("Reference error: '{name}' not declared"));
+        return Err(Error::reference(name.clone()));
     }
 
     pub fn set(&mut self, name: Rc<str>, obj: Value) {
         self.store.borrow_mut().insert(name, obj);
     }
+
+    /// Rebinds `name` in the innermost scope that declares it.
+    pub fn assign(&self, name: Rc<str>, value: Value) -> Result<()> {
+        if self.store.borrow().contains_key(&name) {
+            self.store.borrow_mut().insert(name, value);
+            return Ok(());
+        }
+
+        match &self.outer {
+            Some(outer) => outer.assign(name, value),
+            None => Err(Error::reference(name)),
+        }
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// The variables of a function call or a `for` iteration, in the slots the
+/// [`Resolver`] gave them, and the scope it's nested in. Globals are kept in
+/// an [`Env`] instead.
+#[derive(Debug, PartialEq)]
+pub struct Scope {
+    slots: RefCell<Vec<Option<Value>>>,
+    outer: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    pub fn new(slots: Vec<Value>, outer: Option<Rc<Scope>>) -> Rc<Self> {
+        return Rc::new(Self {
+            slots: RefCell::new(slots.into_iter().map(Some).collect()),
+            outer,
+        });
+    }
+
+    /// The scope `depth` scopes out from this one.
+    fn at(&self, depth: usize) -> &Scope {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.outer.as_deref().expect("a resolved depth");
+        }
+
+        return scope;
+    }
+
+    /// The scope this one is nested in.
+    pub fn outer(&self) -> Option<Rc<Scope>> {
+        return self.outer.clone();
+    }
+
+    /// Fails like a global lookup when the `let` of the slot hasn't run yet.
+    pub fn get(&self, depth: usize, slot: usize, name: &Rc<str>) -> Result<Value> {
+        match self.at(depth).slots.borrow().get(slot) {
+            Some(Some(value)) => Ok(value.clone()),
+            _ => Err(Error::reference(name.clone())),
+        }
+    }
+
+    pub fn set(&self, slot: usize, value: Value) {
+        let mut slots = self.slots.borrow_mut();
+        if slots.len() <= slot {
+            slots.resize(slot + 1, None);
+        }
+        slots[slot] = Some(value);
+    }
+
+    /// Rebinds a slot that was declared already.
+    pub fn assign(&self, depth: usize, slot: usize, name: &Rc<str>, value: Value) -> Result<()> {
+        match self.at(depth).slots.borrow_mut().get_mut(slot) {
+            Some(Some(current)) => {
+                *current = value;
+                Ok(())
+            }
+            _ => Err(Error::reference(name.clone())),
+        }
+    }
 }
 
-pub struct Evaluator<W1: Write, W2: Write> {
+pub struct Evaluator<W: Write> {
+    /// The globals.
     pub env: Env,
-    stdout: W1,
-    stderr: W2,
+    /// The function call or `for` iteration being run, none at the top level.
+    scope: Option<Rc<Scope>>,
+    stdout: W,
+    /// The script functions being run, outermost first.
+    call_stack: Vec<Frame>,
+    /// The call expression being evaluated, which becomes the call site of
+    /// the function it applies, directly or through a method like `map`.
+    call_site: Option<Span>,
+    /// The loops around the code being run, within its function.
+    loops: usize,
 }
 
-impl<W1: Write, W2: Write> Evaluator<W1, W2> {
-    pub fn new(env: Env, stdout: W1, stderr: W2) -> Self {
+impl<W: Write> Evaluator<W> {
+    pub fn new(env: Env, stdout: W) -> Self {
         return Self {
             env,
+            scope: None,
             stdout,
-            stderr,
+            call_stack: Vec::new(),
+            call_site: None,
+            loops: 0,
         };
     }
 
-    pub fn eval(&mut self, statements: Vec<Result<AST>>) -> Value {
+    /// Exposes a Rust function to scripts as `name`. Closures can capture host
+    /// state, and should report bad arguments with [`arity_error`] and
+    /// [`type_error`] so they read like the builtins' errors.
+    pub fn register_fn<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + 'static,
+    {
+        self.env
+            .set(name.into(), Value::Native(NativeFn::new(name, func)));
+    }
+
+    /// Calls the script function bound to `name`, e.g. one defined by a
+    /// program loaded earlier with `eval`, and converts its result.
+    pub fn call_function<T: FromValue>(&mut self, name: &str, args: impl IntoArgs) -> Result<T> {
+        let function = self.env.get(&name.into())?;
+        let result = self.apply_fn(function, args.into_args()?)?;
+
+        return T::from_value(result);
+    }
+
+    /// Runs a parsed program and returns the value of its last statement. A
+    /// program that failed to parse or uses undeclared identifiers isn't run
+    /// at all, and evaluation stops at the first runtime error.
+    pub fn eval(&mut self, statements: Vec<Result<AST>>) -> Result<Value> {
+        let statements = statements.into_iter().collect::<Result<Vec<AST>>>()?;
+        let statements = Resolver::new(&self.env).resolve(statements)?;
+
         let mut result = Value::Idle;
-        for stmt in statements {
-            let ast = match stmt {
-                Ok(ast) => ast,
-                Err(err) => {
-                    writeln!(self.stderr, "{err}").unwrap();
-                    continue;
-                }
-            };
+        for ast in statements {
             if matches!(ast, AST::Return { .. }) {
-                match self.eval_ast(ast) {
-                    Ok(value) => return value,
-                    Err(err) => {
-                        writeln!(self.stderr, "{err}").unwrap();
-                        return Value::Idle;
-                    }
-                }
+                return self.eval_ast(ast);
             }
 
-            let val = self.eval_ast(ast);
-            if let Ok(Value::Return(_)) = val {
-                return val.unwrap();
-            }
-            if let Err(err) = val {
-                writeln!(self.stderr, "{err}").unwrap();
-                return Value::Idle;
+            let val = self.eval_ast(ast)?;
+            if let Value::Return(_) = val {
+                return Ok(val);
             }
 
-            result = val.unwrap();
+            result = val;
         }
 
-        return result;
+        return Ok(result);
     }
 
+    /// Evaluates a node, and tells errors that don't know where they happened
+    /// yet that it was here. The innermost node wins as it sees them first.
     fn eval_ast(&mut self, tree: AST) -> Result<Value> {
+        let span = tree.span();
+        return self.eval_node(tree).map_err(|err| match err.location() {
+            Location::Unknown => err.with_location(Location::Span(span)),
+            _ => err,
+        });
+    }
+
+    fn eval_node(&mut self, tree: AST) -> Result<Value> {
         let to_return = match tree {
-            AST::Type(val) => self.eval_type(val)?,
-            AST::If { condition, yes, no } => self.eval_if(*condition, yes, no)?,
-            AST::Print(val) => {
-                let evaluated = self.eval_ast(*val)?;
-                writeln!(self.stdout, "{evaluated}").unwrap();
-                self.stdout.flush().unwrap();
-                Value::Idle
+            AST::Type(val, _) => self.eval_type(val)?,
+            AST::If {
+                condition, yes, no, ..
+            } => self.eval_if(*condition, yes, no)?,
+            AST::While {
+                condition, body, ..
+            } => self.eval_while(*condition, body)?,
+            AST::For {
+                index,
+                iterable,
+                body,
+                ..
+            } => self.eval_for(index.is_some(), *iterable, body)?,
+            // a stray signal in a function fails at the call, at the top
+            // level there's no call so it fails right here
+            AST::Break(_) | AST::Continue(_) if self.loops == 0 && self.call_stack.is_empty() => {
+                let signal = if matches!(tree, AST::Break(_)) {
+                    Value::Break
+                } else {
+                    Value::Continue
+                };
+                check_loop_signal(signal)?
             }
-            AST::Return { value } => {
+            AST::Break(_) => Value::Break,
+            AST::Continue(_) => Value::Continue,
+            AST::Local {
+                name, depth, slot, ..
+            } => self.local_scope().get(depth, slot, &name)?,
+            AST::Return { value, .. } => {
                 let to_return = self.eval_ast(*value)?;
                 Value::Return(Box::new(to_return))
             }
 
-            AST::Let { ident, value } => {
-                let evaluated = self.eval_ast(*value)?;
-                self.env.set(ident, evaluated);
+            AST::Let {
+                ident,
+                value,
+                doc,
+                slot,
+                ..
+            } => {
+                let mut evaluated = self.eval_ast(*value)?;
+                // anonymous functions go by the first name they're bound to,
+                // and take the docs written for it
+                if let Value::Fn {
+                    name, doc: fn_doc, ..
+                } = &mut evaluated
+                {
+                    if name.is_none() {
+                        *name = Some(ident.clone());
+                    }
+                    if fn_doc.is_none() {
+                        *fn_doc = doc;
+                    }
+                }
+                self.define(ident, slot, evaluated);
                 Value::Idle
             }
 
-            AST::Fn { name, params, body } => {
-                let env = self.env.clone();
+            AST::Fn {
+                name,
+                params,
+                body,
+                doc,
+                slot,
+                ..
+            } => {
+                let scope = self.scope.clone();
                 if let Some(fn_name) = name {
-                    self.env.set(
-                        fn_name.clone(),
-                        Value::Fn {
-                            params: params.clone(),
-                            body: body.clone(),
-                            env,
-                        },
-                    );
+                    let function = Value::Fn {
+                        name: Some(fn_name.clone()),
+                        params: params.clone(),
+                        body: body.clone(),
+                        doc,
+                        scope,
+                    };
+                    self.define(fn_name, slot, function);
 
                     return Ok(Value::Idle);
                 }
 
                 Value::Fn {
+                    name: None,
                     params: params.clone(),
                     body: body.clone(),
-                    env,
+                    doc,
+                    scope,
                 }
             }
 
-            AST::Call { calle, args } => {
+            AST::Call { calle, args, span } => {
+                if let AST::Expr(Op::Dot, mut operands, _) = *calle {
+                    let name = self.member_name(operands.pop().unwrap());
+                    let receiver = self.eval_ast(operands.pop().unwrap())?;
+                    let args = self.eval_expressions(args.to_vec())?;
+                    return self.at_call_site(span, |this| this.call_method(receiver, &name, args));
+                }
+
                 let function = self.eval_ast(*calle)?;
                 let mut result = Vec::new();
                 for item in args.iter() {
                     result.push(self.eval_ast(item.clone())?);
                 }
 
-                return self.apply_fn(function, result);
+                return self.at_call_site(span, |this| this.apply_fn(function, result));
             }
 
-            AST::Expr(op, mut operands) => {
+            AST::Expr(op, mut operands, _) => {
+                if let (Op::And | Op::Or, 2) = (op, operands.len()) {
+                    let right = operands.pop().unwrap();
+                    let left = operands.pop().unwrap();
+                    return self.eval_logical(op, left, right);
+                }
+
                 if operands.len() == 2 {
                     let right = self.eval_ast(operands.pop().unwrap())?;
 
                     match op {
-                        Op::And | Op::Or => {
+                        Op::Index => {
                             let left = self.eval_ast(operands.pop().unwrap())?;
-                            return self.eval_infix_booleans(op, left, right);
+                            return eval_index(left, right);
                         }
 
-                        Op::Index => {
+                        Op::Range | Op::RangeInclusive => {
+                            let left = self.eval_ast(operands.pop().unwrap())?;
+                            return eval_range(op, left, right);
+                        }
+
+                        Op::Dot => {
+                            let receiver = self.eval_ast(operands.pop().unwrap())?;
+                            return eval_member(receiver, &right.to_string());
+                        }
+
+                        Op::AssignEqual | Op::BangEqual => {
                             let left = self.eval_ast(operands.pop().unwrap())?;
-                            return self.eval_index(left, right);
+                            return Ok(eval_equality(op, &left, &right));
                         }
 
-                        Op::AssignEqual => {
+                        Op::Greater | Op::GreaterEqual | Op::Less | Op::LessEqual => {
                             let left = self.eval_ast(operands.pop().unwrap())?;
-                            if let (Value::Number(_), Value::Number(_)) = (&left, &right) {
-                                return self.eval_infix_numbers(op, left, right);
-                            }
-                            return self.eval_infix_booleans(op, left, right);
+                            return eval_comparison(op, left, right);
                         }
 
                         Op::Plus => {
                             let left = self.eval_ast(operands.pop().unwrap())?;
 
-                            if let (Value::Number(_), Value::Number(_)) = (&left, &right) {
-                                return self.eval_infix_numbers(op, left, right);
-                            }
-                            if let (Value::String(lstr), Value::String(rstr)) = (&left, &right) {
-                                let concatenated: Rc<str> =
-                                    format!("{}{}", lstr.to_string(), rstr.to_string()).into();
-                                return Ok(Value::String(concatenated));
-                            }
-
-                            return Err(anyhow!(
-                                "[operation: {op} ] Type mismatch: '{left}' {op} '{right}'"
-                            ));
+                            return eval_plus(left, right);
                         }
 
                         Op::Minus
                         | Op::Star
-                        | Op::Greater
-                        | Op::GreaterEqual
-                        | Op::Less
-                        | Op::LessEqual
-                        | Op::Slash => {
+                        | Op::Slash
+                        | Op::Modulo
+                        | Op::Power
+                        | Op::FloorDiv => {
                             let left = self.eval_ast(operands.pop().unwrap())?;
-                            return self.eval_infix_numbers(op, left, right);
-                        }
-
-                        Op::Push => {
-                            let left = match operands.pop().unwrap() {
-                                AST::Type(Type::Ident(ident)) => ident,
-                                ast => {
-                                    return Err(anyhow!(
-                                        "[operation: PUSH] Error: '{ast}' not an IDENTIFIER"
-                                    ))
-                                }
-                            };
-                            let value = self.env.get(&left)?;
-                            match value {
-                                Value::Array(arr) => arr.borrow_mut().push(right),
-                                Value::String(arr) => {
-                                    if let Value::String(str) = right.clone() {
-                                        let mut string = arr.to_string();
-                                        if str.len() != 1 {
-                                            return Err(anyhow!(
-                                                "[operation: PUSH] Error: Argument to push must be a character, got '{right}'"
-                                            ));
-                                        }
-                                        string.push(str.chars().nth(0).unwrap() as char);
-                                        self.eval_reassign(left, Value::String(string.into()))?;
-                                    }
-                                    return Err(anyhow!(
-                                        "[operation: PUSH] Error: Argument to push must be a character, got '{right}'"
-                                    ));
-                                }
-                                val => {
-                                    return Err(anyhow!(
-                                        "[operation: PUSH] Type mismatch: '{val}' not iterable"
-                                    ))
-                                }
-                            };
-
-                            return Ok(Value::Idle);
+                            return eval_infix_numbers(op, left, right);
                         }
 
-                        Op::ReAssign => {
-                            let left = match operands.pop().unwrap() {
-                                AST::Type(Type::Ident(ident)) => ident,
-                                ast => {
-                                    return Err(anyhow!(
-                                        "[operation: {op} ] Error: '{ast}' not an IDENTIFIER"
-                                    ))
-                                }
-                            };
-
-                            return self.eval_reassign(left, right);
+                        Op::ReAssign
+                        | Op::PlusAssign
+                        | Op::MinusAssign
+                        | Op::StarAssign
+                        | Op::SlashAssign => {
+                            let target = operands.pop().unwrap();
+                            return self.eval_reassign(op, target, right);
                         }
                         operation => panic!("shoul not error, got: '{operation}'"),
                     }
@@ -246,99 +394,12 @@ impl<W1: Write, W2: Write> Evaluator<W1, W2> {
                     Op::Assing => self.eval_ast(operands.pop().unwrap())?,
                     Op::Fn => self.eval_ast(operands.pop().unwrap())?,
 
-                    Op::Rest => {
-                        let value = self.eval_ast(operands.pop().unwrap())?;
-                        match value {
-                            Value::String(str) => {
-                                if str.len() < 1 {
-                                    return Ok(Value::Nil);
-                                }
-                                let string = str.get(1..).unwrap().to_string();
-                                return Ok(Value::String(string.into()));
-                            }
-                            Value::Array(arr) => {
-                                if arr.borrow().len() < 1 {
-                                    return Ok(Value::Nil);
-                                }
-                                let new_arr: Vec<Value> = arr.borrow().get(1..).unwrap().into();
-                                return Ok(Value::Array(Rc::new(RefCell::new(new_arr))));
-                            }
-                            value => {
-                                return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'"))
-                            }
-                        }
-                    }
-
-                    Op::First => {
-                        let value = self.eval_ast(operands.pop().unwrap())?;
-                        if let Value::Array(arr) = value {
-                            if arr.borrow().len() == 0 {
-                                return Ok(Value::Nil);
-                            }
-                            return Ok(arr.borrow()[0].clone());
-                        }
-                        if let Value::String(arr) = value {
-                            if arr.len() == 0 {
-                                return Ok(Value::Nil);
-                            }
-                            return Ok(Value::String(
-                                arr.chars().nth(0).unwrap().to_string().into(),
-                            ));
-                        }
-
-                        return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'"));
-                    }
-
-                    Op::Last => {
-                        let value = self.eval_ast(operands.pop().unwrap())?;
-                        if let Value::Array(arr) = value {
-                            if arr.borrow().len() == 0 {
-                                return Ok(Value::Nil);
-                            }
-                            return Ok(arr.borrow().last().unwrap().clone());
-                        }
-                        if let Value::String(arr) = value {
-                            if arr.len() == 0 {
-                                return Ok(Value::Nil);
-                            }
-                            return Ok(Value::String(
-                                arr.chars().last().unwrap().to_string().into(),
-                            ));
-                        }
-
-                        return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'"));
-                    }
-
-                    Op::Len => {
-                        let value = self.eval_ast(operands.pop().unwrap())?;
-                        if let Value::Array(arr) = value {
-                            return Ok(Value::Number(arr.borrow().len() as f64));
-                        }
-                        if let Value::String(arr) = value {
-                            return Ok(Value::Number(arr.len() as f64));
-                        }
-
-                        return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'"));
-                    }
-                    Op::Minus => {
-                        let val = self.eval_ast(operands.pop().unwrap())?;
-                        if let Value::Number(num) = val {
-                            return Ok(Value::Number(-1.0 * num));
-                        }
-                        return Err(anyhow!("[operation: {op} ] Type mismatch: '{val}'"));
-                    }
-                    Op::Bang => {
+                    Op::Minus | Op::Bang => {
                         let val = self.eval_ast(operands.pop().unwrap())?;
-                        match val {
-                            Value::Bool(val) => Value::Bool(!val),
-                            Value::Nil => Value::Bool(true),
-                            value => {
-                                return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'"))
-                            }
-                        }
+                        return eval_prefix(op, val);
                     }
 
-                    _ => return Err(anyhow!("Unknown operator: {op}")),
+                    _ => return Err(Error::runtime(op, format!("unknown operator in {op}"))),
                 }
             }
         };
@@ -356,188 +417,248 @@ impl<W1: Write, W2: Write> Evaluator<W1, W2> {
         return Ok(result);
     }
 
+    fn local_scope(&self) -> &Scope {
+        return self.scope.as_deref().expect("a scope for resolved locals");
+    }
+
+    /// Binds a declaration to the slot the resolver gave it, or to a global
+    /// at the top level.
+    fn define(&mut self, name: Rc<str>, slot: Option<usize>, value: Value) {
+        match (slot, &self.scope) {
+            (Some(slot), Some(scope)) => scope.set(slot, value),
+            _ => self.env.set(name, value),
+        }
+    }
+
+    /// Runs `call` with `span` as the call site of the functions it applies.
+    fn at_call_site<T>(&mut self, span: Span, call: impl FnOnce(&mut Self) -> T) -> T {
+        let caller = self.call_site.replace(span);
+        let result = call(self);
+        self.call_site = caller;
+
+        return result;
+    }
+
     fn apply_fn(&mut self, function: Value, args: Vec<Value>) -> Result<Value> {
         match function {
-            Value::Fn { params, body, env } => {
-                let mut env_call = Env::new();
-                env_call.outer = Some(Box::new(env.clone()));
-
-                for (i, param) in params.iter().enumerate() {
-                    env_call.set(param.clone(), args[i].clone());
+            Value::Fn {
+                name,
+                params,
+                body,
+                scope,
+                ..
+            } => {
+                if args.len() != params.len() {
+                    return Err(Error::arity("FUNCTION CALL", params.len(), args.len()));
                 }
 
-                let current = self.env.clone();
-                self.env = env_call;
-                let evaluated = self.eval_block_stmt(body)?;
-                self.env = current;
+                // params take the first slots, in order
+                let current = self.scope.replace(Scope::new(args, scope));
+                let loops = std::mem::take(&mut self.loops);
+                self.call_stack.push(Frame {
+                    name,
+                    call_site: self.call_site,
+                });
+                // the innermost call sees the error first, while the whole
+                // stack that led to it is still there
+                let evaluated = self.eval_block_stmt(body).map_err(|err| {
+                    if err.backtrace().is_empty() {
+                        let frames = self.call_stack.iter().rev().cloned().collect();
+                        return err.with_backtrace(frames);
+                    }
+                    return err;
+                });
+                self.call_stack.pop();
+                self.scope = current;
+                self.loops = loops;
+                let evaluated = check_loop_signal(evaluated?)?;
 
                 match evaluated {
                     Value::Return(val) => return Ok(*val),
                     _ => return Ok(evaluated),
                 };
             }
-            _ => Err(anyhow!(
-                "[operation: FUNCTION CALL] Error: not a function '{function}'"
+            Value::Builtin(builtin) => builtin.call(&mut self.stdout, args),
+            Value::Native(native) => native.call(&args),
+            _ => Err(Error::runtime(
+                "FUNCTION CALL",
+                format!("not a function '{function}'"),
             )),
         }
     }
 
     fn eval_block_stmt(&mut self, block: Rc<[AST]>) -> Result<Value> {
-        let mut result = Ok(Value::Nil);
+        let mut result = Value::Nil;
 
         for stmt in block.iter() {
-            result = self.eval_ast(stmt.clone());
-            if matches!(result, Err(_)) {
-                return result;
-            }
-            if matches!(result, Ok(Value::Return(_))) {
-                return result;
+            result = self.eval_ast(stmt.clone())?;
+            if matches!(result, Value::Return(_) | Value::Break | Value::Continue) {
+                return Ok(result);
             }
         }
 
-        return result;
+        return Ok(result);
     }
 
-    fn eval_infix_numbers(&self, op: Op, l_val: Value, r_val: Value) -> Result<Value> {
-        let left = match l_val {
-            Value::Number(num) => num,
-            value => {
-                return Err(anyhow!(
-                    "[operation: {op} ] Type mismatch: '{value}' expected number"
-                ))
-            }
-        };
-
-        let right = match r_val {
-            Value::Number(num) => num,
-            value => {
-                return Err(anyhow!(
-                    "[operation: {op} ] Type mismatch: '{value}' expected number"
+    /// `and`/`or` only evaluate the right side when the left one doesn't
+    /// decide the result, and return the deciding operand itself.
+    fn eval_logical(&mut self, op: Op, left: AST, right: AST) -> Result<Value> {
+        let left = self.eval_ast(left)?;
+        let decided = match op {
+            Op::And => !is_truth(left.clone()),
+            Op::Or => is_truth(left.clone()),
+            _ => {
+                return Err(Error::runtime(
+                    op,
+                    format!("unknown operator in {left} '{op}'"),
                 ))
             }
         };
 
-        let result = match op {
-            Op::Plus => Value::Number(left + right),
-            Op::Minus => Value::Number(left - right),
-            Op::Star => Value::Number(left * right),
-            Op::Slash => {
-                if right == 0.0 {
-                    return Err(anyhow!("Dividing by zero error: {left} '{op}' {right}"));
-                }
-                Value::Number(left / right)
-            }
-            Op::Greater => Value::Bool(left > right),
-            Op::GreaterEqual => Value::Bool(left >= right),
-            Op::Less => Value::Bool(left < right),
-            Op::LessEqual => Value::Bool(left <= right),
-            Op::AssignEqual => Value::Bool(left == right),
-            Op::BangEqual => Value::Bool(left != right),
-            _ => return Err(anyhow!("Unknown operator: {left} '{op}' {right}")),
-        };
-
-        return Ok(result);
-    }
-
-    fn eval_infix_booleans(&self, op: Op, l_val: Value, r_val: Value) -> Result<Value> {
-        let left = match l_val {
-            Value::Bool(num) => num,
-            value => return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'")),
-        };
-
-        let right = match r_val {
-            Value::Bool(num) => num,
-            value => return Err(anyhow!("[operation: {op} ] Type mismatch: '{value}'")),
-        };
-
-        let result = match op {
-            Op::And => Value::Bool(left && right),
-            Op::Or => Value::Bool(left || right),
-            Op::AssignEqual => Value::Bool(left == right),
-            Op::BangEqual => Value::Bool(left != right),
-            _ => return Err(anyhow!("Unknown operator: {left} '{op}' {right}")),
-        };
+        if decided {
+            return Ok(left);
+        }
 
-        return Ok(result);
+        return self.eval_ast(right);
     }
 
     fn eval_if(&mut self, condition: AST, yes: Rc<[AST]>, no: Option<Rc<[AST]>>) -> Result<Value> {
         let condition = self.eval_ast(condition)?;
-        if self.is_truth(condition) {
+        if is_truth(condition) {
             return self.eval_block_stmt(yes);
-        } else if !matches!(no, None) {
+        } else if no.is_some() {
             return self.eval_block_stmt(no.unwrap());
         } else {
             return Ok(Value::Nil);
         }
     }
 
-    fn is_truth(&self, val: Value) -> bool {
-        match val {
-            Value::Bool(false) => false,
-            Value::Nil => false,
-            _ => true,
+    fn eval_while(&mut self, condition: AST, body: Rc<[AST]>) -> Result<Value> {
+        loop {
+            let evaluated = self.eval_ast(condition.clone())?;
+            if !is_truth(evaluated) {
+                break;
+            }
+
+            self.loops += 1;
+            let evaluated = self.eval_block_stmt(body.clone());
+            self.loops -= 1;
+
+            match evaluated? {
+                Value::Return(val) => return Ok(Value::Return(val)),
+                Value::Break => break,
+                _ => continue,
+            }
         }
+
+        return Ok(Value::Idle);
     }
 
-    fn eval_index(&mut self, arr: Value, num: Value) -> Result<Value> {
-        let index = match num {
-            Value::Number(n) => n as usize,
-            value => {
-                return Err(anyhow!(
-                    "[operation: INDEX] Type mismatch: '{value}' not a number"
-                ))
-            }
-        };
+    fn eval_for(&mut self, indexed: bool, iterable: AST, body: Rc<[AST]>) -> Result<Value> {
+        let iterable = self.eval_ast(iterable)?;
+        let keyed = matches!(iterable, Value::Map(_));
 
-        match arr {
-            Value::Array(arr) => {
-                if index >= arr.borrow().len() {
-                    return Err(anyhow!("[operation: INDEX] Error: index out of bounds"));
-                }
-                Ok(arr.borrow()[index].clone())
-            }
-            Value::String(str) => {
-                if index >= str.len() {
-                    return Err(anyhow!("[operation: INDEX] Error: index out of bounds"));
-                }
-                let retorno = str.chars().nth(index).unwrap().to_string();
+        for (position, element) in iterate(iterable)? {
+            // the loop variables take the first slots, index before item
+            let slots = if indexed {
+                vec![position, element]
+            } else if keyed {
+                vec![position]
+            } else {
+                vec![element]
+            };
 
-                Ok(Value::String(retorno.into()))
+            let scope = Scope::new(slots, self.scope.clone());
+            let current = self.scope.replace(scope);
+            self.loops += 1;
+            let evaluated = self.eval_block_stmt(body.clone());
+            self.loops -= 1;
+            self.scope = current;
+
+            match evaluated? {
+                Value::Return(val) => return Ok(Value::Return(val)),
+                Value::Break => break,
+                _ => continue,
             }
-            value => Err(anyhow!(
-                "[operation: INDEX] Type mismatch: '{value}' not iterable"
-            )),
         }
+
+        return Ok(Value::Idle);
     }
 
-    fn eval_reassign(&mut self, ident: Rc<str>, value: Value) -> Result<Value> {
-        let mut current_env = self.env.clone();
-        loop {
-            if current_env.store.borrow().contains_key(&ident) {
-                current_env.set(ident.clone(), value);
-                return Ok(Value::Idle);
+    /// Stores `value` into a place expression: a variable or an index into an
+    /// array or map. Compound operators combine it with the current value first.
+    fn eval_reassign(&mut self, op: Op, target: AST, value: Value) -> Result<Value> {
+        match target {
+            AST::Type(Type::Ident(ident), _) => {
+                let value = if op == Op::ReAssign {
+                    value
+                } else {
+                    eval_compound(op, self.env.get(&ident)?, value)?
+                };
+                self.env.assign(ident, value)?;
+                Ok(Value::Idle)
             }
-
-            if let Some(outer) = current_env.outer {
-                current_env = *outer.clone();
-            } else {
-                break;
+            AST::Local {
+                name, depth, slot, ..
+            } => {
+                let scope = self.local_scope();
+                let value = if op == Op::ReAssign {
+                    value
+                } else {
+                    eval_compound(op, scope.get(depth, slot, &name)?, value)?
+                };
+                scope.assign(depth, slot, &name, value)?;
+                Ok(Value::Idle)
+            }
+            AST::Expr(place @ (Op::Index | Op::Dot), mut operands, _) => {
+                let key = match place {
+                    Op::Dot => Value::String(self.member_name(operands.pop().unwrap())),
+                    _ => self.eval_ast(operands.pop().unwrap())?,
+                };
+                let container = self.eval_ast(operands.pop().unwrap())?;
+                // read even for `=`, so a bad index fails like it does when reading
+                let current = eval_index(container.clone(), key.clone())?;
+                let value = eval_compound(op, current, value)?;
+                assign_index(container, key, value)
             }
+            ast => Err(Error::runtime(op, format!("'{ast}' is not assignable"))),
         }
+    }
 
-        return Err(anyhow!("Reference error: '{ident}' not declared"));
+    /// The parser stores the name after `.` as a string literal.
+    fn member_name(&self, name: AST) -> Rc<str> {
+        match name {
+            AST::Type(Type::String(name), _) => name,
+            ast => ast.to_string().into(),
+        }
     }
 
     fn eval_type(&mut self, value: Type) -> Result<Value> {
         let evaluated = match value {
             Type::Bool(bool) => Value::Bool(bool),
             Type::String(str) => Value::String(str),
-            Type::Number(num) => Value::Number(num),
+            Type::Int(num) => Value::Int(num),
+            Type::Float(num) => Value::Float(num),
             Type::Arr(vec) => {
                 let list = RefCell::new(self.eval_expressions(*vec)?);
                 Value::Array(Rc::new(list))
             }
+            Type::Template(parts) => {
+                let mut string = String::new();
+                for part in parts.into_iter() {
+                    let evaluated = self.eval_ast(part)?;
+                    string.push_str(&evaluated.to_string());
+                }
+                Value::String(string.into())
+            }
+            Type::Map(pairs) => {
+                let mut map = HashMap::new();
+                for (key, value) in pairs.into_iter() {
+                    let key = HashKey::try_from(self.eval_ast(key)?)?;
+                    map.insert(key, self.eval_ast(value)?);
+                }
+                Value::Map(Rc::new(RefCell::new(map)))
+            }
             Type::Nil => Value::Nil,
             Type::Ident(ident) => self.env.get(&ident)?,
         };
@@ -548,20 +669,68 @@ impl<W1: Write, W2: Write> Evaluator<W1, W2> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
-    Number(f64),
+    Int(i64),
+    Float(f64),
     Array(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<HashMap<HashKey, Value>>>),
+    Range {
+        start: Option<i64>,
+        end: Option<i64>,
+        inclusive: bool,
+    },
     Ident(Rc<str>),
     String(Rc<str>),
     Bool(bool),
     Return(Box<Value>),
+    Break,
+    Continue,
     Idle,
     Nil,
 
     Fn {
+        /// The name it was declared or first bound with, shown in backtraces.
+        name: Option<Rc<str>>,
         params: Rc<[Rc<str>]>,
         body: Rc<[AST]>,
-        env: Env,
+        /// Its doc comment, shown by `help`.
+        doc: Option<Rc<str>>,
+        /// The scope it was declared in.
+        scope: Option<Rc<Scope>>,
     },
+    /// A function compiled for the VM.
+    Closure {
+        name: Option<Rc<str>>,
+        function: Rc<Function>,
+        doc: Option<Rc<str>>,
+        /// The scope it was declared in.
+        scope: Option<Rc<Scope>>,
+    },
+    Builtin(Builtin),
+    Native(NativeFn),
+}
+
+impl Value {
+    pub fn is_number(&self) -> bool {
+        return matches!(self, Value::Int(_) | Value::Float(_));
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Range { .. } => "range",
+            Value::Ident(_) => "identifier",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Fn { .. } | Value::Closure { .. } | Value::Builtin(_) | Value::Native(_) => {
+                "function"
+            }
+            Value::Nil => "nil",
+            Value::Return(_) | Value::Break | Value::Continue | Value::Idle => "statement",
+        }
+    }
 }
 
 impl fmt::Display for Value {
@@ -570,9 +739,27 @@ impl fmt::Display for Value {
             Value::Bool(val) => write!(f, "{val}"),
             Value::Ident(ident) => write!(f, "{ident}"),
             Value::String(val) => write!(f, "{val}"),
-            Value::Number(val) => write!(f, "{val}"),
+            Value::Int(val) => write!(f, "{val}"),
+            // floats always show a fraction so they can't be mistaken for ints
+            Value::Float(val) => write!(f, "{val:?}"),
             Value::Return(value) => write!(f, "{}", *value),
             Value::Nil => write!(f, "nil"),
+            Value::Builtin(builtin) => write!(f, "<builtin {}>", builtin.name),
+            Value::Native(native) => write!(f, "<native {}>", native.name),
+            Value::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if let Some(start) = start {
+                    write!(f, "{start}")?;
+                }
+                write!(f, "{}", if *inclusive { "..=" } else { ".." })?;
+                if let Some(end) = end {
+                    write!(f, "{end}")?;
+                }
+                Ok(())
+            }
             Value::Array(arr) => {
                 write!(f, "[")?;
                 for (i, element) in arr.borrow().iter().enumerate() {
@@ -583,55 +770,1032 @@ impl fmt::Display for Value {
                 }
                 write!(f, "]")
             }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in sorted_entries(&map.borrow()).into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                write!(f, "}}")
+            }
             _ => write!(f, "\0"),
         }
     }
 }
 
+/// Turns a possibly negative index into a position inside a collection of `len` elements.
+fn resolve_index(index: i64, len: usize) -> Result<usize> {
+    let resolved = if index < 0 { len as i64 + index } else { index };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(Error::index(index, len));
+    }
+
+    return Ok(resolved as usize);
+}
+
+/// Resolves slice bounds the way Python does: negative bounds count from the end
+/// and anything past either end is clamped, so slicing never fails.
+fn slice_bounds(
+    start: Option<i64>,
+    end: Option<i64>,
+    inclusive: bool,
+    len: usize,
+) -> (usize, usize) {
+    let len = len as i64;
+    let resolve = |bound: i64| if bound < 0 { len + bound } else { bound };
+
+    let start = start.map_or(0, resolve).clamp(0, len);
+    let end = match end {
+        Some(end) if inclusive => resolve(end).saturating_add(1),
+        Some(end) => resolve(end),
+        None => len,
+    }
+    .clamp(start, len);
+
+    return (start as usize, end as usize);
+}
+
+/// Deep equality: numbers compare across int and float, collections element by
+/// element and functions by identity.
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => l == r,
+        (Value::Int(l), Value::Float(r)) | (Value::Float(r), Value::Int(l)) => *l as f64 == *r,
+        (Value::Float(l), Value::Float(r)) => l == r,
+        (Value::String(l), Value::String(r)) => l == r,
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        (Value::Nil, Value::Nil) => true,
+        (Value::Array(l), Value::Array(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| values_equal(l, r))
+        }
+        (Value::Map(l), Value::Map(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len()
+                && l.iter()
+                    .all(|(key, l)| r.get(key).is_some_and(|r| values_equal(l, r)))
+        }
+        (Value::Range { .. }, Value::Range { .. }) => left == right,
+        (Value::Fn { body: l, .. }, Value::Fn { body: r, .. }) => Rc::ptr_eq(l, r),
+        (Value::Closure { function: l, .. }, Value::Closure { function: r, .. }) => {
+            Rc::ptr_eq(l, r)
+        }
+        (Value::Builtin(l), Value::Builtin(r)) => l == r,
+        (Value::Native(l), Value::Native(r)) => l == r,
+        _ => false,
+    }
+}
+
+/// Ordering used by `< <= > >=`, `None` when the values can't be compared.
+fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+        (Value::Int(l), Value::Float(r)) => (*l as f64).partial_cmp(r),
+        (Value::Float(l), Value::Int(r)) => l.partial_cmp(&(*r as f64)),
+        (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+        (Value::Array(l), Value::Array(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            for (l, r) in l.iter().zip(r.iter()) {
+                match compare_values(l, r)? {
+                    Ordering::Equal => continue,
+                    ordering => return Some(ordering),
+                }
+            }
+            Some(l.len().cmp(&r.len()))
+        }
+        _ => None,
+    }
+}
+
+/// Map entries ordered by key, so printing and iterating a map is deterministic.
+fn sorted_entries(map: &HashMap<HashKey, Value>) -> Vec<(HashKey, Value)> {
+    let mut pairs: Vec<_> = map
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    return pairs;
+}
+
+/// Key of a `Value::Map`. Only values with a stable identity can be used as keys,
+/// floats are required to be integral and address the same entry as the int.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub enum HashKey {
+    Bool(bool),
+    Int(i64),
+    String(Rc<str>),
+}
+
+impl TryFrom<Value> for HashKey {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Bool(val) => Ok(HashKey::Bool(val)),
+            Value::String(val) => Ok(HashKey::String(val)),
+            Value::Int(num) => Ok(HashKey::Int(num)),
+            Value::Float(num) if num == num.trunc() && num.abs() < i64::MAX as f64 => {
+                Ok(HashKey::Int(num as i64))
+            }
+            value => Err(Error::type_mismatch(
+                "MAP",
+                format!("'{value}' unusable as map key"),
+            )),
+        }
+    }
+}
+
+impl From<HashKey> for Value {
+    fn from(key: HashKey) -> Self {
+        match key {
+            HashKey::Bool(val) => Value::Bool(val),
+            HashKey::Int(num) => Value::Int(num),
+            HashKey::String(val) => Value::String(val),
+        }
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashKey::Bool(val) => write!(f, "{val}"),
+            HashKey::Int(num) => write!(f, "{num}"),
+            HashKey::String(val) => write!(f, "{val}"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use std::io;
-
-    use anyhow::Result;
+    use std::{
+        cell::{Cell, RefCell},
+        io,
+        rc::Rc,
+    };
+
+    use crate::{
+        error::{ArithmeticError::*, Error, Location, Result},
+        eval::Value,
+        lexer::Span,
+        parser::Parser,
+        vm::Vm,
+    };
+
+    use super::{arity_error, type_error, Env, Evaluator, IntoValue};
+
+    /// The tables below check what went wrong, `error_locations` checks where.
+    fn without_location(err: Error) -> Error {
+        return err.with_location(Location::Unknown);
+    }
 
-    use crate::{eval::Value, parser::Parser};
+    /// Runs `code` on the tree walker and on the VM, which have to agree on
+    /// the result, down to where errors happened, and on what was printed.
+    fn eval_both(code: &str) -> Result<Value> {
+        return eval_both_printing(code).0;
+    }
 
-    use super::{Env, Evaluator};
+    /// [`eval_both`], also giving back what the program printed.
+    fn eval_both_printing(code: &str) -> (Result<Value>, String) {
+        let (mut printed, mut vm_printed) = (Vec::new(), Vec::new());
+        let result =
+            Evaluator::new(Env::new(), &mut printed).eval(Parser::new(code.into()).parse());
+        let vm_result = Vm::new(Env::new(), &mut vm_printed).eval(Parser::new(code.into()).parse());
+
+        assert_eq!(result, vm_result, "engines disagree on {code:?}");
+        assert_eq!(
+            printed, vm_printed,
+            "engines print differently for {code:?}"
+        );
+        return (result, String::from_utf8(printed).unwrap());
+    }
 
     #[test]
     fn functions_calls() -> Result<()> {
-        let input: Vec<(&'static str, Value)> = [
+        let input: Vec<(&'static str, Result<Value>)> = [
             (
                 "let identity = fn(x) { x; }; identity(5);",
-                Value::Number(5.0),
+                Ok(Value::Int(5)),
             ),
             (
                 "let identity = fn(x) { return x; }; identity(5);",
-                Value::Number(5.0),
+                Ok(Value::Int(5)),
             ),
             (
                 "let double = fn(x) { x * 2; }; double(5);",
-                Value::Number(10.0),
+                Ok(Value::Int(10)),
             ),
             (
                 "let add = fn(x, y) { x + y; }; add(5, 5);",
-                Value::Number(10.0),
+                Ok(Value::Int(10)),
             ),
             (
                 "let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));",
-                Value::Number(20.0),
+                Ok(Value::Int(20)),
             ),
         ]
         .into();
 
         for (code, expected) in input {
-            let program = Parser::new(code.into()).parse();
-            let result = Evaluator::new(Env::new(), io::stdout(), io::stderr()).eval(program);
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn scopes() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            // a local declared further down doesn't hide the global yet
+            (
+                "let x = 1; fn f() { let y = x; let x = 2; y + x } f();",
+                Ok(Value::Int(3)),
+            ),
+            (
+                "fn f(n) { let n = n * 2; n } f(4);",
+                Ok(Value::Int(8)),
+            ),
+            (
+                "fn counter() { let n = 0; fn() { n += 1; n } } let c = counter(); c(); c();",
+                Ok(Value::Int(2)),
+            ),
+            (
+                "fn f() { fn even(n) { if n == 0 { true } else { odd(n - 1) } } fn odd(n) { if n == 0 { false } else { even(n - 1) } } even(10) } f();",
+                Ok(Value::Bool(true)),
+            ),
+            // declared by the time the next turn of the loop reads it
+            (
+                "fn f() { let i = 0; let total = 0; while i < 3 { if i > 0 { total += prev; } let prev = i; i += 1; } total } f();",
+                Ok(Value::Int(1)),
+            ),
+            (
+                "let total = 0; for i, x in [5, 6] { let y = x * 2; total += i + y; } total;",
+                Ok(Value::Int(23)),
+            ),
+            (
+                "for c in [false] { if c { let x = 1; } x; }",
+                Err(Error::reference("x")),
+            ),
+            // the closure is bound to the global, the `let` further down doesn't change that
+            (
+                "let x = 1; fn f() { let g = fn() { x }; let before = g(); let x = 2; [before, g()] } f();",
+                vec![1, 1].into_value(),
+            ),
+            ("fn f() { y } 1;", Err(Error::reference("y"))),
+            // only a loop comes back around to a use above the `let`
+            ("fn f() { y; let y = 1; } 1;", Err(Error::reference("y"))),
+            ("fn f() { y = 1; } 1;", Err(Error::reference("y"))),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
 
-            println!("expected: {expected}, got: {result}");
+            println!("expected: {expected:?}, got: {result:?}");
             assert_eq!(expected, result);
         }
 
-        anyhow::Ok(())
+        Ok(())
+    }
+
+    #[test]
+    fn late_shadowing() {
+        let code =
+            "let x = 1; fn f() { let g = fn() { x }; print(g()); let x = 2; print(g()); } f()";
+        let (result, printed) = eval_both_printing(code);
+
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!("1\n1\n", printed);
+    }
+
+    #[test]
+    fn while_loops() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (
+                "let i = 0; let sum = 0; while i < 5 { i = i + 1; sum = sum + i; } sum;",
+                Ok(Value::Int(15)),
+            ),
+            (
+                "let i = 0; while true { if i == 3 { break; } i = i + 1; } i;",
+                Ok(Value::Int(3)),
+            ),
+            (
+                "let i = 0; let count = 0; while i < 6 { i = i + 1; if i == 2 { continue; } count = count + 1; } count;",
+                Ok(Value::Int(5)),
+            ),
+            (
+                "let find = fn() { let i = 0; while true { if i == 4 { return i; } i = i + 1; } }; find();",
+                Ok(Value::Int(4)),
+            ),
+            ("let f = fn() { break; }; f();", Err(Error::runtime("BREAK", "'break' outside of a loop"))),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn for_loops() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (
+                "let sum = 0; for x in [1, 2, 3] { sum = sum + x; } sum;",
+                Ok(Value::Int(6)),
+            ),
+            (
+                r#"let out = ""; for i, ch in "text" { if i != 1 { out = out + ch; } } out;"#,
+                Ok(Value::String("txt".into())),
+            ),
+            (
+                "let sum = 0; for i in 0..5 { sum = sum + i; } sum;",
+                Ok(Value::Int(10)),
+            ),
+            (
+                "let sum = 0; for i in 1..=4 { if i == 3 { break; } sum = sum + i; } sum;",
+                Ok(Value::Int(3)),
+            ),
+            (
+                r#"let keys = ""; for k, v in {"b": 2, "a": 1} { keys = keys + k; } keys;"#,
+                Ok(Value::String("ab".into())),
+            ),
+            (
+                "let fns = []; for i in 0..3 { push(fns, fn() { i }); } fns[1]();",
+                Ok(Value::Int(1)),
+            ),
+            (
+                "for x in [1] { let hidden = x; } hidden;",
+                Err(Error::reference("hidden")),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn indexing_and_slicing() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("[1, 2, 3][-1];", Ok(Value::Int(3))),
+            ("[1, 2, 3][-3];", Ok(Value::Int(1))),
+            ("[1, 2, 3][3];", Err(Error::index(3, 3))),
+            ("[1, 2, 3][-4];", Err(Error::index(-4, 3))),
+            (
+                "[1, 2, 3][0.5];",
+                Err(Error::type_mismatch("INDEX", "'0.5' not an integer")),
+            ),
+            (r#""hello"[-2];"#, Ok(Value::String("l".into()))),
+            ("len([1, 2, 3, 4][1..3]);", Ok(Value::Int(2))),
+            ("[1, 2, 3, 4][1..=2][1];", Ok(Value::Int(3))),
+            ("[1, 2, 3, 4][-2..][0];", Ok(Value::Int(3))),
+            ("len([1, 2, 3, 4][..10]);", Ok(Value::Int(4))),
+            ("len([1, 2, 3, 4][3..1]);", Ok(Value::Int(0))),
+            (r#""hello"[2..];"#, Ok(Value::String("llo".into()))),
+            (r#""hello"[..-1];"#, Ok(Value::String("hell".into()))),
+            (r#""hello"[..];"#, Ok(Value::String("hello".into()))),
+            (
+                "let sum = 0; for i in ..3 { sum = sum + i; } sum;",
+                Ok(Value::Int(3)),
+            ),
+            (
+                "for i in 3.. { print(i); }",
+                Err(Error::runtime("FOR", "unbounded range '3..' not iterable")),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_operators() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("7 % 3;", Ok(Value::Int(1))),
+            ("-7 % 3;", Ok(Value::Int(2))),
+            ("7 % -3;", Ok(Value::Int(-2))),
+            ("7 // 2;", Ok(Value::Int(3))),
+            ("-7 // 2;", Ok(Value::Int(-4))),
+            ("2 ** 10;", Ok(Value::Int(1024))),
+            ("2 ** 3 ** 2;", Ok(Value::Int(512))),
+            ("-2 ** 2;", Ok(Value::Int(-4))),
+            ("2 ** -1;", Ok(Value::Float(0.5))),
+            (
+                "let is_even = fn(n) { n % 2 == 0 }; is_even(10);",
+                Ok(Value::Bool(true)),
+            ),
+            ("1 % 0;", Err(Error::arithmetic(DivisionByZero, "1 '%' 0"))),
+            (
+                "1 // 0;",
+                Err(Error::arithmetic(DivisionByZero, "1 '//' 0")),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn assignments() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("let x = 1; x += 2; x;", Ok(Value::Int(3))),
+            (
+                "let x = 10; x -= 2; x *= 3; x /= 4; x;",
+                Ok(Value::Float(6.0)),
+            ),
+            (
+                r#"let s = "a"; s += "b"; s;"#,
+                Ok(Value::String("ab".into())),
+            ),
+            (
+                "let arr = [1, 2, 3]; arr[0] = 9; arr[0];",
+                Ok(Value::Int(9)),
+            ),
+            (
+                "let arr = [1, 2, 3]; arr[-1] += 1; arr[2];",
+                Ok(Value::Int(4)),
+            ),
+            (
+                "let grid = [[1, 2], [3, 4]]; grid[1][0] = 7; grid[1][0];",
+                Ok(Value::Int(7)),
+            ),
+            (
+                r#"let m = {}; m["a"] = 1; m["a"] += 1; m["a"];"#,
+                Ok(Value::Int(2)),
+            ),
+            (
+                r#"let m = {"xs": [1]}; m["xs"][0] = 5; m["xs"][0];"#,
+                Ok(Value::Int(5)),
+            ),
+            ("let arr = [1]; arr[1] = 2;", Err(Error::index(1, 1))),
+            (
+                r#"let s = "ab"; s[0] = "c";"#,
+                Err(Error::runtime(
+                    "INDEX",
+                    "strings are immutable, build a new one instead",
+                )),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn strings() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (r#""tab\there";"#, Ok(Value::String("tab\there".into()))),
+            (
+                r#"let name = "x"; let n = 2; "${name} has ${n * 2} items";"#,
+                Ok(Value::String("x has 4 items".into())),
+            ),
+            (
+                r#"let m = {"k": [1, 2]}; "m: ${m["k"]}, ${len(m["k"])}";"#,
+                Ok(Value::String("m: [1, 2], 2".into())),
+            ),
+            (
+                r#""\${not} ${"${1}"}";"#,
+                Ok(Value::String("${not} 1".into())),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn numbers() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("2 + 3 * 4;", Ok(Value::Int(14))),
+            ("9007199254740993 + 1;", Ok(Value::Int(9007199254740994))),
+            ("1 + 0.5;", Ok(Value::Float(1.5))),
+            ("2.0 * 3;", Ok(Value::Float(6.0))),
+            ("6 / 3;", Ok(Value::Float(2.0))),
+            ("7 // 2.0;", Ok(Value::Float(3.0))),
+            ("1 == 1.0;", Ok(Value::Bool(true))),
+            ("2 < 2.5;", Ok(Value::Bool(true))),
+            (
+                "9223372036854775807 + 1;",
+                Err(Error::arithmetic(Overflow, "9223372036854775807 '+' 1")),
+            ),
+            (
+                "-9223372036854775807 - 2;",
+                Err(Error::arithmetic(Overflow, "-9223372036854775807 '-' 2")),
+            ),
+            (
+                "3037000500 * 3037000500;",
+                Err(Error::arithmetic(Overflow, "3037000500 '*' 3037000500")),
+            ),
+            ("2 ** 64;", Err(Error::arithmetic(Overflow, "2 '**' 64"))),
+            (
+                "[1, 2, 3][1.0];",
+                Err(Error::type_mismatch("INDEX", "'1.0' not an integer")),
+            ),
+            (
+                "0..1.5;",
+                Err(Error::type_mismatch("..", "'1.5' expected integer")),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn methods() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("[1, 2, 3].len();", Ok(Value::Int(3))),
+            (r#""abc".upper();"#, Ok(Value::String("ABC".into()))),
+            ("let xs = [1, 2, 3]; xs.rest().first();", Ok(Value::Int(2))),
+            (
+                r#""a,b,c".split(",").reverse().join("-");"#,
+                Ok(Value::String("c-b-a".into())),
+            ),
+            (
+                "[1, 2, 3, 4].filter(fn(x) { x % 2 == 0 }).map(fn(x) { x * 10 }).last();",
+                Ok(Value::Int(40)),
+            ),
+            ("let xs = [1]; xs.push(2).push(3); xs.len();", Ok(Value::Int(3))),
+            (r#"let m = {"a": 1, "b": 2}; m.keys().join("");"#, Ok(Value::String("ab".into()))),
+            (r#"let m = {"name": "x"}; m.name;"#, Ok(Value::String("x".into()))),
+            (r#"let m = {}; m.count = 1; m.count += 1; m["count"];"#, Ok(Value::Int(2))),
+            (
+                r#"let counter = {"n": 0}; counter.bump = fn() { counter.n += 1; }; counter.bump(); counter.n;"#,
+                Ok(Value::Int(1)),
+            ),
+            (r#""abc".nope();"#, Err(Error::runtime("METHOD", "string has no method 'nope'"))),
+            (r#""abc".upper(1);"#, Err(Error::arity("upper", 0, 1))),
+            ("true.len;", Err(Error::type_mismatch(".", "bool 'true' has no field 'len'"))),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unicode_strings() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (r#"len("héllo");"#, Ok(Value::Int(5))),
+            (r#""😀👍".len();"#, Ok(Value::Int(2))),
+            (r#""héllo"[1];"#, Ok(Value::String("é".into()))),
+            (r#""héllo"[-1];"#, Ok(Value::String("o".into()))),
+            (r#""héllo"[5];"#, Err(Error::index(5, 5))),
+            (r#""😀ab"[1..];"#, Ok(Value::String("ab".into()))),
+            (r#"first("😀ab");"#, Ok(Value::String("😀".into()))),
+            (r#"last("ab😀");"#, Ok(Value::String("😀".into()))),
+            (r#"rest("😀ab");"#, Ok(Value::String("ab".into()))),
+            (r#""éa".rest().rest();"#, Ok(Value::String("".into()))),
+            (
+                r#""né".chars().join("-");"#,
+                Ok(Value::String("n-é".into())),
+            ),
+            (
+                r#""né".bytes().join(" ");"#,
+                Ok(Value::String("110 195 169".into())),
+            ),
+            (r#""😀".bytes().len();"#, Ok(Value::Int(4))),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn equality_and_ordering() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (r#""a" == "a";"#, Ok(Value::Bool(true))),
+            ("nil == nil;", Ok(Value::Bool(true))),
+            ("[1] == [1];", Ok(Value::Bool(true))),
+            ("[1, [2, 3]] == [1, [2, 3.0]];", Ok(Value::Bool(true))),
+            ("[1, 2] != [1];", Ok(Value::Bool(true))),
+            ("true != false;", Ok(Value::Bool(true))),
+            (r#"1 == "1";"#, Ok(Value::Bool(false))),
+            ("nil != false;", Ok(Value::Bool(true))),
+            ("let f = fn() { 1 }; f == f;", Ok(Value::Bool(true))),
+            (
+                "let f = fn() { 1 }; let g = fn() { 1 }; f == g;",
+                Ok(Value::Bool(false)),
+            ),
+            ("0..2 == 0..2;", Ok(Value::Bool(true))),
+            (r#""apple" < "banana";"#, Ok(Value::Bool(true))),
+            (r#""b" >= "abc";"#, Ok(Value::Bool(true))),
+            ("[1, 2] < [1, 3];", Ok(Value::Bool(true))),
+            ("[1, 2] < [1, 2, 0];", Ok(Value::Bool(true))),
+            ("[2] <= [1, 9];", Ok(Value::Bool(false))),
+            ("[1, 2].contains(2.0);", Ok(Value::Bool(true))),
+            (
+                r#"1 < "2";"#,
+                Err(Error::type_mismatch(
+                    "<",
+                    "cannot compare int '1' with string '2'",
+                )),
+            ),
+            (
+                r#"[1] < ["a"];"#,
+                Err(Error::type_mismatch(
+                    "<",
+                    "cannot compare array '[1]' with array '[a]'",
+                )),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn logical_operators() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("true and false;", Ok(Value::Bool(false))),
+            ("false or true;", Ok(Value::Bool(true))),
+            ("1 and 2;", Ok(Value::Int(2))),
+            ("nil and 2;", Ok(Value::Nil)),
+            ("0 or 2;", Ok(Value::Int(0))),
+            (
+                r#"let name = nil or "default"; name;"#,
+                Ok(Value::String("default".into())),
+            ),
+            (r#""" or "default";"#, Ok(Value::String("".into()))),
+            (
+                "let x = nil; x != nil and x.len() > 0;",
+                Ok(Value::Bool(false)),
+            ),
+            (
+                "let x = [1]; x != nil and x.len() > 0;",
+                Ok(Value::Bool(true)),
+            ),
+            ("true or 1 / 0;", Ok(Value::Bool(true))),
+            ("false and 1 / 0;", Ok(Value::Bool(false))),
+            (
+                "false or 1 / 0;",
+                Err(Error::arithmetic(DivisionByZero, "1 '/' 0")),
+            ),
+            (
+                "let i = 0; let f = fn() { i += 1; true }; false and f(); i;",
+                Ok(Value::Int(0)),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn builtins() -> Result<()> {
+        let array = |items: Vec<Value>| Value::Array(Rc::new(RefCell::new(items)));
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (
+                "let xs = [1, 2]; push(xs, 3); xs;",
+                Ok(array(vec![Value::Int(1), Value::Int(2), Value::Int(3)])),
+            ),
+            (
+                "[[1], [1, 2]].map(len);",
+                Ok(array(vec![Value::Int(1), Value::Int(2)])),
+            ),
+            ("let f = first; f([4, 5]);", Ok(Value::Int(4))),
+            (r#"push("ab", "c");"#, Ok(Value::String("abc".into()))),
+            ("let len = 5; len;", Ok(Value::Int(5))),
+            ("let f = fn(last) { last }; f(3);", Ok(Value::Int(3))),
+            ("len == len;", Ok(Value::Bool(true))),
+            ("len == first;", Ok(Value::Bool(false))),
+            ("len(1, 2);", Err(Error::arity("len", 1, 2))),
+            (
+                r#"push("ab", "cd");"#,
+                Err(Error::runtime(
+                    "push",
+                    "Argument to push must be a character, got 'cd'",
+                )),
+            ),
+            (
+                "/// Adds them.\n/// Ints only.\nfn add(a, b) { a + b } help(add);",
+                Ok(Value::String("fn add(a, b)\nAdds them.\nInts only.".into())),
+            ),
+            (
+                "/// Doubles it.\nlet double = fn(x) { x * 2 }; help(double);",
+                Ok(Value::String("fn double(x)\nDoubles it.".into())),
+            ),
+            ("help(fn() { 1 });", Ok(Value::String("fn()".into()))),
+            ("help(len);", Ok(Value::String("builtin fn len".into()))),
+            (
+                "help(1);",
+                Err(type_error("help", &Value::Int(1), "function")),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        let program = Parser::new(r#"print("a", 1); print([1, 2]);"#.into()).parse();
+        let mut evaluator = Evaluator::new(Env::new(), Vec::new());
+        assert_eq!(Ok(Value::Idle), evaluator.eval(program));
+        assert_eq!(
+            "a 1\n[1, 2]\n",
+            String::from_utf8(evaluator.stdout).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn native_functions() -> Result<()> {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+
+        let mut evaluator = Evaluator::new(Env::new(), io::stdout());
+        evaluator.register_fn("add", |args| match args {
+            [Value::Int(l), Value::Int(r)] => Ok(Value::Int(l + r)),
+            [l, r] => Err(type_error("add", if l.is_number() { r } else { l }, "int")),
+            _ => Err(arity_error("add", 2, args.len())),
+        });
+        evaluator.register_fn("tick", move |_| {
+            counter.set(counter.get() + 1);
+            Ok(Value::Int(counter.get()))
+        });
+
+        let input: Vec<(&'static str, Result<Value>)> = [
+            ("add(1, 2);", Ok(Value::Int(3))),
+            ("tick(); tick();", Ok(Value::Int(2))),
+            ("[1, 2].map(fn(x) { add(x, 10) }).len();", Ok(Value::Int(2))),
+            ("let plus = add; plus(2, 2);", Ok(Value::Int(4))),
+            ("add == add;", Ok(Value::Bool(true))),
+            ("add == tick;", Ok(Value::Bool(false))),
+            ("add(1);", Err(arity_error("add", 2, 1))),
+            (
+                r#"add(1, "2");"#,
+                Err(type_error("add", &Value::String("2".into()), "int")),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let program = Parser::new(code.into()).parse();
+            let result = evaluator.eval(program).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        assert_eq!(2, calls.get());
+        assert_eq!(
+            "[operation: add ] Error: expected 2 argument(s), got 1",
+            arity_error("add", 2, 1).to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn calling_script_functions() -> Result<()> {
+        let code = r#"
+            fn score(item) { item["price"] * item["count"] }
+            fn names(xs) { xs.map(fn(x) { x.upper() }) }
+            fn find(xs, x) { if xs.contains(x) { x } else { nil } }
+            fn half(x) { x / 2 }
+            fn id(x) { x }
+        "#;
+        let mut evaluator = Evaluator::new(Env::new(), io::stdout());
+        evaluator.eval(Parser::new(code.into()).parse())?;
+
+        let item = evaluator.eval(Parser::new(r#"{"price": 3, "count": 4};"#.into()).parse())?;
+        assert_eq!(12, evaluator.call_function::<i64>("score", vec![item])?);
+
+        let names: Vec<String> = evaluator.call_function("names", (vec!["a", "b"],))?;
+        assert_eq!(vec!["A", "B"], names);
+
+        let found: Option<u8> = evaluator.call_function("find", (vec![1, 2], 2))?;
+        assert_eq!(Some(2), found);
+        let found: Option<u8> = evaluator.call_function("find", (vec![1, 2], 3))?;
+        assert_eq!(None, found);
+
+        assert_eq!(1.5, evaluator.call_function::<f64>("half", (3,))?);
+        assert_eq!(
+            Value::Nil,
+            evaluator.call_function("find", (Vec::<i64>::new(), None::<i64>))?
+        );
+
+        let errors = [
+            evaluator.call_function::<i64>("half", ()).unwrap_err(),
+            evaluator.call_function::<String>("id", (1.5,)).unwrap_err(),
+            evaluator.call_function::<u8>("id", (-1,)).unwrap_err(),
+            evaluator.call_function::<i64>("missing", ()).unwrap_err(),
+        ];
+        let expected = [
+            Error::arity("FUNCTION CALL", 1, 0),
+            type_error("convert", &Value::Float(1.5), "string"),
+            Error::runtime("convert", "-1 out of range for u8"),
+            Error::reference("missing"),
+        ];
+        assert_eq!(expected, errors);
+
+        let overflow = Error::arithmetic(Overflow, "18446744073709551615 as int");
+        assert_eq!(Err(overflow.clone()), u64::MAX.into_value());
+        assert_eq!(
+            Err(overflow),
+            evaluator.call_function::<Value>("id", (u64::MAX,))
+        );
+        assert_eq!(Ok(Value::Int(i64::MAX)), (i64::MAX as usize).into_value());
+
+        Ok(())
+    }
+
+    #[test]
+    fn maps() -> Result<()> {
+        let input: Vec<(&'static str, Result<Value>)> = [
+            (
+                r#"let m = {"name": "x", "age": 3}; m["age"];"#,
+                Ok(Value::Int(3)),
+            ),
+            (
+                r#"let m = {1: "one", true: "yes"}; m[1.0];"#,
+                Ok(Value::String("one".into())),
+            ),
+            (r#"let m = {"a": 1}; m["missing"];"#, Ok(Value::Nil)),
+            (
+                r#"{"a": 1}[1..2];"#,
+                Err(Error::type_mismatch("MAP", "'1..2' unusable as map key")),
+            ),
+            (r#"len({"a": 1, "b": 2});"#, Ok(Value::Int(2))),
+            (
+                r#"{"a": [1, 2], "b": 2} == {"b": 2, "a": [1, 2]};"#,
+                Ok(Value::Bool(true)),
+            ),
+            (r#"{"a": 1} != {"a": 2};"#, Ok(Value::Bool(true))),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code).map_err(without_location);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(expected, result);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_locations() -> Result<()> {
+        let input: Vec<(&'static str, Location)> = [
+            (
+                "let x = 1;\nlet y = x + z;",
+                Location::Span(Span::new(23, 1, 2, 13)),
+            ),
+            // errors inside a function point into its body, not at the call
+            (
+                "fn f(a) {\n  a / 0\n}\nf(1);",
+                Location::Span(Span::new(12, 5, 2, 3)),
+            ),
+            (
+                "let xs = [1, 2];\nxs[5];",
+                Location::Span(Span::new(17, 5, 2, 1)),
+            ),
+            // at the top level there's no call to blame, only the signal itself
+            ("let x = 1;\nbreak;", Location::Span(Span::new(11, 6, 2, 1))),
+            (
+                "if true {\n  continue;\n}",
+                Location::Span(Span::new(12, 9, 2, 3)),
+            ),
+        ]
+        .into();
+
+        for (code, expected) in input {
+            let result = eval_both(code);
+
+            println!("expected: {expected:?}, got: {result:?}");
+            assert_eq!(Some(expected), result.err().map(|err| err.location()));
+        }
+
+        let program = Parser::new("let x = 1;\nlet y = x + z;".into()).parse();
+        assert_eq!(
+            "[line: 2, column: 13] Reference error: 'z' not declared",
+            Evaluator::new(Env::new(), io::stdout())
+                .eval(program)
+                .unwrap_err()
+                .to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_backtraces() -> Result<()> {
+        let code = "\
+fn inner(x) { x / 0 }
+let outer = fn(x) { inner(x) };
+[1].map(fn(x) { outer(x) });";
+        let err = eval_both(code).unwrap_err();
+
+        let frames: Vec<(Option<&str>, usize)> = err
+            .backtrace()
+            .iter()
+            .map(|frame| (frame.name.as_deref(), frame.call_site.unwrap().line))
+            .collect();
+        assert_eq!(
+            vec![(Some("inner"), 2), (Some("outer"), 3), (None, 3)],
+            frames
+        );
+        assert_eq!(
+            "[line: 1, column: 15] Dividing by zero error: 1 '/' 0
+    in fn 'inner' called at line 2
+    in fn 'outer' called at line 3
+    in anonymous fn called at line 3",
+            err.to_string()
+        );
+
+        // calls from the host have no call site
+        let mut evaluator = Evaluator::new(Env::new(), io::stdout());
+        evaluator.eval(Parser::new("fn f() { 1 / 0 }".into()).parse())?;
+        let err = evaluator.call_function::<Value>("f", ()).unwrap_err();
+        assert_eq!(
+            "[line: 1, column: 10] Dividing by zero error: 1 '/' 0\n    in fn 'f' called by the host",
+            err.to_string()
+        );
+
+        Ok(())
     }
 }
@@ -0,0 +1,175 @@
+use std::{fmt, io::Write, rc::Rc};
+
+use super::{
+    methods::{expect_args, sequence_method},
+    Value,
+};
+use crate::error::{Error, Result};
+
+/// Signature shared by every builtin, `out` is the evaluator's stdout.
+pub type BuiltinFn = fn(out: &mut dyn Write, args: Vec<Value>) -> Result<Value>;
+
+#[derive(Debug, Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    func: BuiltinFn,
+}
+
+impl Builtin {
+    pub(crate) fn call(&self, out: &mut dyn Write, args: Vec<Value>) -> Result<Value> {
+        return (self.func)(out, args);
+    }
+}
+
+/// Builtins are unique by name, comparing the function pointers isn't reliable.
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        return self.name == other.name;
+    }
+}
+
+/// Shared so natives stay cheap to clone along with `Value`.
+type NativeFnPtr = Rc<dyn Fn(&[Value]) -> Result<Value>>;
+
+/// Host function installed with `register_fn`, it may capture
+/// state from the embedding program.
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: Rc<str>,
+    func: NativeFnPtr,
+}
+
+impl NativeFn {
+    pub fn new(name: &str, func: impl Fn(&[Value]) -> Result<Value> + 'static) -> Self {
+        return Self {
+            name: name.into(),
+            func: Rc::new(func),
+        };
+    }
+
+    pub(crate) fn call(&self, args: &[Value]) -> Result<Value> {
+        return (self.func)(args);
+    }
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("NativeFn")
+            .field("name", &self.name)
+            .finish_non_exhaustive();
+    }
+}
+
+/// Two natives are the same only if they were registered from the same closure.
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        return Rc::ptr_eq(&self.func, &other.func);
+    }
+}
+
+/// The error every builtin reports when called with the wrong number of
+/// arguments, native functions should use it too.
+pub fn arity_error(name: &str, expected: usize, got: usize) -> Error {
+    return Error::arity(name, expected, got);
+}
+
+/// The error every builtin reports for an argument of the wrong type, e.g.
+/// `type_error("upper", &value, "string")`.
+pub fn type_error(name: &str, value: &Value, expected: &str) -> Error {
+    return Error::type_mismatch(name, format!("'{value}' expected {expected}"));
+}
+
+/// Functions visible from every scope unless shadowed. Adding a builtin only
+/// takes a new entry here.
+const BUILTINS: &[(&str, BuiltinFn)] = &[
+    ("len", |_, args| sequence("len", args)),
+    ("first", |_, args| sequence("first", args)),
+    ("last", |_, args| sequence("last", args)),
+    ("rest", |_, args| sequence("rest", args)),
+    ("push", push),
+    ("print", print),
+    ("help", help),
+];
+
+/// Looked up by `Env::get` once a name isn't bound in any scope.
+pub(super) fn lookup(name: &str) -> Option<Value> {
+    return BUILTINS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(name, func)| Value::Builtin(Builtin { name, func: *func }));
+}
+
+fn sequence(name: &str, args: Vec<Value>) -> Result<Value> {
+    let [value] = expect_args(name, args)?;
+    return sequence_method(name, value);
+}
+
+/// Arrays are updated in place, strings are immutable so a new one is returned.
+fn push(_: &mut dyn Write, args: Vec<Value>) -> Result<Value> {
+    let [collection, item] = expect_args("push", args)?;
+    match (collection, item) {
+        (Value::Array(arr), item) => {
+            arr.borrow_mut().push(item);
+            Ok(Value::Array(arr))
+        }
+        (Value::String(str), Value::String(ch)) if ch.chars().count() == 1 => {
+            Ok(Value::String(format!("{str}{ch}").into()))
+        }
+        (Value::String(_), item) => Err(Error::runtime(
+            "push",
+            format!("Argument to push must be a character, got '{item}'"),
+        )),
+        (value, _) => Err(Error::type_mismatch(
+            "push",
+            format!("'{value}' not iterable"),
+        )),
+    }
+}
+
+fn print(out: &mut dyn Write, args: Vec<Value>) -> Result<Value> {
+    let line = args
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    writeln!(out, "{line}")
+        .and_then(|_| out.flush())
+        .map_err(|err| Error::runtime("print", err.to_string()))?;
+
+    return Ok(Value::Idle);
+}
+
+/// The signature of a function followed by its doc comment, if it has one.
+fn help(_: &mut dyn Write, args: Vec<Value>) -> Result<Value> {
+    let [function] = expect_args("help", args)?;
+    let help = match function {
+        Value::Fn {
+            name, params, doc, ..
+        } => signature(name, &params, doc),
+        Value::Closure {
+            name,
+            function,
+            doc,
+            ..
+        } => signature(name, &function.params, doc),
+        Value::Builtin(builtin) => format!("builtin fn {}", builtin.name),
+        Value::Native(native) => format!("native fn {}", native.name),
+        value => return Err(type_error("help", &value, "function")),
+    };
+
+    return Ok(Value::String(help.into()));
+}
+
+/// `fn name(params)` and the doc comment below it.
+fn signature(name: Option<Rc<str>>, params: &[Rc<str>], doc: Option<Rc<str>>) -> String {
+    let params = params.join(", ");
+    let signature = match name {
+        Some(name) => format!("fn {name}({params})"),
+        None => format!("fn({params})"),
+    };
+    return match doc {
+        Some(doc) => format!("{signature}\n{doc}"),
+        None => signature,
+    };
+}
@@ -0,0 +1,188 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{type_error, Value};
+use crate::error::{ArithmeticError, Error, Result};
+
+/// Conversion of Rust values into script values, used for the arguments of
+/// `call_function`. Integers too large for an `int` fail.
+pub trait IntoValue {
+    fn into_value(self) -> Result<Value>;
+}
+
+/// Conversion of script values back into Rust, used for the result of
+/// `call_function`.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+/// The arguments of `call_function`, a tuple or a `Vec` of values converted
+/// with [`IntoValue`].
+pub trait IntoArgs {
+    fn into_args(self) -> Result<Vec<Value>>;
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Result<Value> {
+        return Ok(self);
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self> {
+        return Ok(value);
+    }
+}
+
+macro_rules! int_conversions {
+    ($($int:ty),*) => {$(
+        impl IntoValue for $int {
+            fn into_value(self) -> Result<Value> {
+                return i64::try_from(self).map(Value::Int).map_err(|_| {
+                    Error::arithmetic(ArithmeticError::Overflow, format!("{self} as int"))
+                });
+            }
+        }
+
+        impl FromValue for $int {
+            fn from_value(value: Value) -> Result<Self> {
+                match value {
+                    Value::Int(num) => <$int>::try_from(num).map_err(|_| {
+                        Error::runtime(
+                            "convert",
+                            format!("{num} out of range for {}", stringify!($int)),
+                        )
+                    }),
+                    value => Err(type_error("convert", &value, "int")),
+                }
+            }
+        }
+    )*};
+}
+
+int_conversions!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Result<Value> {
+        return Ok(Value::Float(self));
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Float(num) => Ok(num),
+            Value::Int(num) => Ok(num as f64),
+            value => Err(type_error("convert", &value, "float")),
+        }
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Result<Value> {
+        return Ok(Value::Float(self as f64));
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> Result<Self> {
+        return f64::from_value(value).map(|num| num as f32);
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Result<Value> {
+        return Ok(Value::Bool(self));
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Bool(val) => Ok(val),
+            value => Err(type_error("convert", &value, "bool")),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Result<Value> {
+        return Ok(Value::String(self.into()));
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Result<Value> {
+        return Ok(Value::String(self.into()));
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::String(str) => Ok(str.to_string()),
+            value => Err(type_error("convert", &value, "string")),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Result<Value> {
+        let items = self
+            .into_iter()
+            .map(IntoValue::into_value)
+            .collect::<Result<_>>()?;
+        return Ok(Value::Array(Rc::new(RefCell::new(items))));
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Array(arr) => arr.borrow().iter().cloned().map(T::from_value).collect(),
+            value => Err(type_error("convert", &value, "array")),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoArgs for Vec<T> {
+    fn into_args(self) -> Result<Vec<Value>> {
+        return self.into_iter().map(IntoValue::into_value).collect();
+    }
+}
+
+macro_rules! tuple_args {
+    ($($arg:ident $value:ident),*) => {
+        impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+            fn into_args(self) -> Result<Vec<Value>> {
+                let ($($value,)*) = self;
+                return Ok(vec![$($value.into_value()?),*]);
+            }
+        }
+    };
+}
+
+tuple_args!();
+tuple_args!(A a);
+tuple_args!(A a, B b);
+tuple_args!(A a, B b, C c);
+tuple_args!(A a, B b, C c, D d);
+tuple_args!(A a, B b, C c, D d, E e);
+tuple_args!(A a, B b, C c, D d, E e, F f);
+
+/// `None` is `nil`.
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Result<Value> {
+        return self.map_or(Ok(Value::Nil), IntoValue::into_value);
+    }
+}
+
+/// Both `nil` and the absence of a value, as returned by a function ending in a
+/// statement, become `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Nil | Value::Idle => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
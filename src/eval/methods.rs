@@ -0,0 +1,288 @@
+use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
+
+use super::{
+    builtins::{arity_error, type_error},
+    ops::is_truth,
+    sorted_entries, values_equal, Evaluator, HashKey, Value,
+};
+use crate::error::{Error, Result};
+
+impl<W: Write> Evaluator<W> {
+    /// Dispatches `receiver.name(args)` to the built-in methods of the
+    /// receiver's type. Maps first look for a function stored under `name`, so
+    /// they double as simple objects.
+    pub(super) fn call_method(
+        &mut self,
+        receiver: Value,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Value> {
+        match receiver {
+            Value::Map(map) => {
+                let stored = map.borrow().get(&HashKey::String(name.into())).cloned();
+                match stored {
+                    Some(function) => self.apply_fn(function, args),
+                    None => map_method(map, name, args),
+                }
+            }
+            Value::Array(arr) => self.array_method(arr, name, args),
+            Value::String(str) => string_method(str, name, args),
+            value => Err(no_method(&value, name)),
+        }
+    }
+
+    fn array_method(
+        &mut self,
+        arr: Rc<RefCell<Vec<Value>>>,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Value> {
+        match name {
+            "map" | "filter" => {
+                let [function] = expect_args(name, args)?;
+                let items = arr.borrow().clone();
+                let mut result = Vec::new();
+                for item in items {
+                    let evaluated = self.apply_fn(function.clone(), vec![item.clone()])?;
+                    if name == "map" {
+                        result.push(evaluated);
+                    } else if is_truth(evaluated) {
+                        result.push(item);
+                    }
+                }
+                Ok(Value::Array(Rc::new(RefCell::new(result))))
+            }
+            _ => array_method(arr, name, args),
+        }
+    }
+}
+
+/// `receiver.name` without a call, only maps have fields.
+pub(crate) fn eval_member(receiver: Value, name: &str) -> Result<Value> {
+    match receiver {
+        Value::Map(map) => Ok(map
+            .borrow()
+            .get(&HashKey::String(name.into()))
+            .cloned()
+            .unwrap_or(Value::Nil)),
+        value => Err(Error::type_mismatch(
+            ".",
+            format!("{} '{value}' has no field '{name}'", value.type_name()),
+        )),
+    }
+}
+
+/// The array methods that don't call back into a script function.
+pub(crate) fn array_method(
+    arr: Rc<RefCell<Vec<Value>>>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value> {
+    let result = match name {
+        "len" | "first" | "last" | "rest" => {
+            let [] = expect_args(name, args)?;
+            return sequence_method(name, Value::Array(arr));
+        }
+        "push" => {
+            let [item] = expect_args(name, args)?;
+            arr.borrow_mut().push(item);
+            Value::Array(arr)
+        }
+        "pop" => {
+            let [] = expect_args(name, args)?;
+            let popped = arr.borrow_mut().pop();
+            popped.unwrap_or(Value::Nil)
+        }
+        "contains" => {
+            let [item] = expect_args(name, args)?;
+            let found = arr.borrow().iter().any(|other| values_equal(other, &item));
+            Value::Bool(found)
+        }
+        "reverse" => {
+            let [] = expect_args(name, args)?;
+            let reversed: Vec<Value> = arr.borrow().iter().rev().cloned().collect();
+            Value::Array(Rc::new(RefCell::new(reversed)))
+        }
+        "join" => {
+            let [separator] = expect_args(name, args)?;
+            let separator = expect_string(name, separator)?;
+            let joined = arr
+                .borrow()
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(&separator);
+            Value::String(joined.into())
+        }
+        _ => return Err(no_method(&Value::Array(arr), name)),
+    };
+
+    return Ok(result);
+}
+
+pub(crate) fn string_method(str: Rc<str>, name: &str, args: Vec<Value>) -> Result<Value> {
+    let result = match name {
+        "len" | "first" | "last" | "rest" => {
+            let [] = expect_args(name, args)?;
+            return sequence_method(name, Value::String(str));
+        }
+        "chars" => {
+            let [] = expect_args(name, args)?;
+            let chars: Vec<Value> = str
+                .chars()
+                .map(|ch| Value::String(ch.to_string().into()))
+                .collect();
+            Value::Array(Rc::new(RefCell::new(chars)))
+        }
+        "bytes" => {
+            let [] = expect_args(name, args)?;
+            let bytes: Vec<Value> = str.bytes().map(|byte| Value::Int(byte as i64)).collect();
+            Value::Array(Rc::new(RefCell::new(bytes)))
+        }
+        "upper" => {
+            let [] = expect_args(name, args)?;
+            Value::String(str.to_uppercase().into())
+        }
+        "lower" => {
+            let [] = expect_args(name, args)?;
+            Value::String(str.to_lowercase().into())
+        }
+        "trim" => {
+            let [] = expect_args(name, args)?;
+            Value::String(str.trim().into())
+        }
+        "contains" | "starts_with" | "ends_with" => {
+            let [pattern] = expect_args(name, args)?;
+            let pattern = expect_string(name, pattern)?;
+            Value::Bool(match name {
+                "contains" => str.contains(&*pattern),
+                "starts_with" => str.starts_with(&*pattern),
+                _ => str.ends_with(&*pattern),
+            })
+        }
+        "split" => {
+            let [separator] = expect_args(name, args)?;
+            let separator = expect_string(name, separator)?;
+            let parts: Vec<Value> = if separator.is_empty() {
+                str.chars()
+                    .map(|ch| Value::String(ch.to_string().into()))
+                    .collect()
+            } else {
+                str.split(&*separator)
+                    .map(|part| Value::String(part.into()))
+                    .collect()
+            };
+            Value::Array(Rc::new(RefCell::new(parts)))
+        }
+        "replace" => {
+            let [from, to] = expect_args(name, args)?;
+            let from = expect_string(name, from)?;
+            let to = expect_string(name, to)?;
+            Value::String(str.replace(&*from, &to).into())
+        }
+        "repeat" => {
+            let [count] = expect_args(name, args)?;
+            match count {
+                Value::Int(count) if count >= 0 => Value::String(str.repeat(count as usize).into()),
+                value => return Err(type_error(name, &value, "a non-negative integer")),
+            }
+        }
+        _ => return Err(no_method(&Value::String(str), name)),
+    };
+
+    return Ok(result);
+}
+
+pub(crate) fn map_method(
+    map: Rc<RefCell<HashMap<HashKey, Value>>>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value> {
+    let result = match name {
+        "len" => {
+            let [] = expect_args(name, args)?;
+            return sequence_method(name, Value::Map(map));
+        }
+        "keys" | "values" => {
+            let [] = expect_args(name, args)?;
+            let entries = sorted_entries(&map.borrow());
+            let items: Vec<Value> = entries
+                .into_iter()
+                .map(|(key, value)| if name == "keys" { key.into() } else { value })
+                .collect();
+            Value::Array(Rc::new(RefCell::new(items)))
+        }
+        "contains" => {
+            let [key] = expect_args(name, args)?;
+            let key = HashKey::try_from(key)?;
+            let found = map.borrow().contains_key(&key);
+            Value::Bool(found)
+        }
+        _ => return Err(no_method(&Value::Map(map), name)),
+    };
+
+    return Ok(result);
+}
+
+/// `len`, `first`, `last` and `rest`, shared by the builtin functions and the
+/// methods of the same name. Strings are sequences of chars (Unicode scalar
+/// values) here just like when indexing them, `bytes()` gives the UTF-8 view.
+pub(super) fn sequence_method(name: &str, value: Value) -> Result<Value> {
+    let result = match (name, value) {
+        ("len", Value::Array(arr)) => Value::Int(arr.borrow().len() as i64),
+        ("len", Value::String(str)) => Value::Int(str.chars().count() as i64),
+        ("len", Value::Map(map)) => Value::Int(map.borrow().len() as i64),
+
+        ("first", Value::Array(arr)) => arr.borrow().first().cloned().unwrap_or(Value::Nil),
+        ("first", Value::String(str)) => match str.chars().next() {
+            Some(ch) => Value::String(ch.to_string().into()),
+            None => Value::Nil,
+        },
+
+        ("last", Value::Array(arr)) => arr.borrow().last().cloned().unwrap_or(Value::Nil),
+        ("last", Value::String(str)) => match str.chars().last() {
+            Some(ch) => Value::String(ch.to_string().into()),
+            None => Value::Nil,
+        },
+
+        ("rest", Value::Array(arr)) => {
+            if arr.borrow().is_empty() {
+                return Ok(Value::Nil);
+            }
+            let new_arr: Vec<Value> = arr.borrow()[1..].into();
+            Value::Array(Rc::new(RefCell::new(new_arr)))
+        }
+        ("rest", Value::String(str)) => {
+            if str.is_empty() {
+                return Ok(Value::Nil);
+            }
+            let mut chars = str.chars();
+            chars.next();
+            Value::String(chars.as_str().into())
+        }
+
+        (name, value) => return Err(Error::type_mismatch(name, format!("'{value}'"))),
+    };
+
+    return Ok(result);
+}
+
+pub(crate) fn expect_args<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N]> {
+    return args
+        .try_into()
+        .map_err(|args: Vec<Value>| arity_error(name, N, args.len()));
+}
+
+fn expect_string(name: &str, value: Value) -> Result<Rc<str>> {
+    match value {
+        Value::String(str) => Ok(str),
+        value => Err(type_error(name, &value, "string")),
+    }
+}
+
+pub(crate) fn no_method(value: &Value, name: &str) -> Error {
+    return Error::runtime(
+        "METHOD",
+        format!("{} has no method '{name}'", value.type_name()),
+    );
+}
@@ -0,0 +1,415 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use super::{
+    compare_values, resolve_index, slice_bounds, sorted_entries, values_equal, HashKey, Value,
+};
+use crate::{
+    error::{ArithmeticError, Error, Result},
+    parser::Op,
+};
+
+pub(crate) fn eval_plus(left: Value, right: Value) -> Result<Value> {
+    if left.is_number() && right.is_number() {
+        return eval_infix_numbers(Op::Plus, left, right);
+    }
+    if let (Value::String(lstr), Value::String(rstr)) = (&left, &right) {
+        let concatenated: Rc<str> = format!("{lstr}{rstr}").into();
+        return Ok(Value::String(concatenated));
+    }
+
+    return Err(Error::type_mismatch("+", format!("'{left}' + '{right}'")));
+}
+
+/// Integer operands stay integers and fail loudly on overflow. As soon as
+/// one side is a float both are promoted, and `/` always divides as floats.
+pub(crate) fn eval_infix_numbers(op: Op, l_val: Value, r_val: Value) -> Result<Value> {
+    match (l_val, r_val) {
+        (Value::Int(left), Value::Int(right)) if op != Op::Slash => {
+            eval_infix_ints(op, left, right)
+        }
+        (Value::Int(left), Value::Int(right)) => eval_infix_floats(op, left as f64, right as f64),
+        (Value::Int(left), Value::Float(right)) => eval_infix_floats(op, left as f64, right),
+        (Value::Float(left), Value::Int(right)) => eval_infix_floats(op, left, right as f64),
+        (Value::Float(left), Value::Float(right)) => eval_infix_floats(op, left, right),
+        (left, right) => {
+            let value = if left.is_number() { right } else { left };
+            Err(Error::type_mismatch(
+                op,
+                format!("'{value}' expected number"),
+            ))
+        }
+    }
+}
+
+pub(crate) fn eval_infix_ints(op: Op, left: i64, right: i64) -> Result<Value> {
+    let overflow =
+        || Error::arithmetic(ArithmeticError::Overflow, format!("{left} '{op}' {right}"));
+    if right == 0 && matches!(op, Op::Modulo | Op::FloorDiv) {
+        return Err(Error::arithmetic(
+            ArithmeticError::DivisionByZero,
+            format!("{left} '{op}' {right}"),
+        ));
+    }
+
+    let result = match op {
+        Op::Plus => Value::Int(left.checked_add(right).ok_or_else(overflow)?),
+        Op::Minus => Value::Int(left.checked_sub(right).ok_or_else(overflow)?),
+        Op::Star => Value::Int(left.checked_mul(right).ok_or_else(overflow)?),
+        Op::Modulo => {
+            // the result takes the sign of the divisor, matching `//` flooring
+            let rem = left.checked_rem(right).ok_or_else(overflow)?;
+            if rem != 0 && (rem < 0) != (right < 0) {
+                Value::Int(rem + right)
+            } else {
+                Value::Int(rem)
+            }
+        }
+        Op::FloorDiv => {
+            let quotient = left.checked_div(right).ok_or_else(overflow)?;
+            if left % right != 0 && (left < 0) != (right < 0) {
+                Value::Int(quotient - 1)
+            } else {
+                Value::Int(quotient)
+            }
+        }
+        Op::Power if right < 0 => Value::Float((left as f64).powf(right as f64)),
+        Op::Power => {
+            let exponent = u32::try_from(right).map_err(|_| overflow())?;
+            Value::Int(left.checked_pow(exponent).ok_or_else(overflow)?)
+        }
+        Op::Greater => Value::Bool(left > right),
+        Op::GreaterEqual => Value::Bool(left >= right),
+        Op::Less => Value::Bool(left < right),
+        Op::LessEqual => Value::Bool(left <= right),
+        Op::AssignEqual => Value::Bool(left == right),
+        Op::BangEqual => Value::Bool(left != right),
+        _ => {
+            return Err(Error::runtime(
+                op,
+                format!("unknown operator in {left} '{op}' {right}"),
+            ))
+        }
+    };
+
+    return Ok(result);
+}
+
+pub(crate) fn eval_infix_floats(op: Op, left: f64, right: f64) -> Result<Value> {
+    let result = match op {
+        Op::Plus => Value::Float(left + right),
+        Op::Minus => Value::Float(left - right),
+        Op::Star => Value::Float(left * right),
+        Op::Slash => {
+            if right == 0.0 {
+                return Err(Error::arithmetic(
+                    ArithmeticError::DivisionByZero,
+                    format!("{left} '{op}' {right}"),
+                ));
+            }
+            Value::Float(left / right)
+        }
+        Op::Modulo => {
+            if right == 0.0 {
+                return Err(Error::arithmetic(
+                    ArithmeticError::DivisionByZero,
+                    format!("{left} '{op}' {right}"),
+                ));
+            }
+            let rem = left % right;
+            if rem != 0.0 && (rem < 0.0) != (right < 0.0) {
+                Value::Float(rem + right)
+            } else {
+                Value::Float(rem)
+            }
+        }
+        Op::FloorDiv => {
+            if right == 0.0 {
+                return Err(Error::arithmetic(
+                    ArithmeticError::DivisionByZero,
+                    format!("{left} '{op}' {right}"),
+                ));
+            }
+            Value::Float((left / right).floor())
+        }
+        Op::Power => Value::Float(left.powf(right)),
+        Op::Greater => Value::Bool(left > right),
+        Op::GreaterEqual => Value::Bool(left >= right),
+        Op::Less => Value::Bool(left < right),
+        Op::LessEqual => Value::Bool(left <= right),
+        Op::AssignEqual => Value::Bool(left == right),
+        Op::BangEqual => Value::Bool(left != right),
+        _ => {
+            return Err(Error::runtime(
+                op,
+                format!("unknown operator in {left} '{op}' {right}"),
+            ))
+        }
+    };
+
+    return Ok(result);
+}
+
+/// `==` and `!=` are defined for every pair of values, values of different
+/// types are simply not equal.
+pub(crate) fn eval_equality(op: Op, left: &Value, right: &Value) -> Value {
+    let equal = values_equal(left, right);
+    return Value::Bool(if op == Op::AssignEqual { equal } else { !equal });
+}
+
+/// Numbers compare numerically, strings and arrays lexicographically.
+pub(crate) fn eval_comparison(op: Op, left: Value, right: Value) -> Result<Value> {
+    if left.is_number() && right.is_number() {
+        return eval_infix_numbers(op, left, right);
+    }
+
+    let ordering = match compare_values(&left, &right) {
+        Some(ordering) => ordering,
+        None => {
+            return Err(Error::type_mismatch(
+                op,
+                format!(
+                    "cannot compare {} '{left}' with {} '{right}'",
+                    left.type_name(),
+                    right.type_name()
+                ),
+            ))
+        }
+    };
+
+    let result = match op {
+        Op::Greater => ordering == Ordering::Greater,
+        Op::GreaterEqual => ordering != Ordering::Less,
+        Op::Less => ordering == Ordering::Less,
+        Op::LessEqual => ordering != Ordering::Greater,
+        _ => {
+            return Err(Error::runtime(
+                op,
+                format!("unknown operator in {left} '{op}' {right}"),
+            ))
+        }
+    };
+
+    return Ok(Value::Bool(result));
+}
+
+/// Yields `(position, element)` pairs in the same order `first`/`rest` walk
+/// a collection: arrays by element, strings by character, ranges by number
+/// and maps by key.
+pub(crate) fn iterate(value: Value) -> Result<Box<dyn Iterator<Item = (Value, Value)>>> {
+    let position = |i: usize| Value::Int(i as i64);
+
+    match value {
+        Value::Array(arr) => {
+            let items = arr.borrow().clone();
+            Ok(Box::new(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, item)| (position(i), item)),
+            ))
+        }
+        Value::String(str) => {
+            let chars: Vec<char> = str.chars().collect();
+            Ok(Box::new(chars.into_iter().enumerate().map(
+                move |(i, ch)| (position(i), Value::String(ch.to_string().into())),
+            )))
+        }
+        Value::Range { end: None, .. } => Err(Error::runtime(
+            "FOR",
+            format!("unbounded range '{value}' not iterable"),
+        )),
+        Value::Range {
+            start,
+            end: Some(end),
+            inclusive,
+        } => {
+            let start = start.unwrap_or(0);
+            let end = if inclusive {
+                end.saturating_add(1)
+            } else {
+                end
+            };
+            Ok(Box::new(
+                (start..end)
+                    .enumerate()
+                    .map(move |(i, num)| (position(i), Value::Int(num))),
+            ))
+        }
+        Value::Map(map) => {
+            let pairs = sorted_entries(&map.borrow());
+            Ok(Box::new(
+                pairs.into_iter().map(|(key, value)| (key.into(), value)),
+            ))
+        }
+        value => Err(Error::type_mismatch(
+            "FOR",
+            format!("'{value}' not iterable"),
+        )),
+    }
+}
+
+/// Loop signals only make sense inside `eval_while`, anywhere else they
+/// escaped the loop they belong to.
+pub(crate) fn check_loop_signal(value: Value) -> Result<Value> {
+    match value {
+        Value::Break => Err(Error::runtime("BREAK", "'break' outside of a loop")),
+        Value::Continue => Err(Error::runtime("CONTINUE", "'continue' outside of a loop")),
+        value => Ok(value),
+    }
+}
+
+pub(crate) fn is_truth(val: Value) -> bool {
+    !matches!(val, Value::Bool(false) | Value::Nil)
+}
+
+pub(crate) fn eval_index(arr: Value, num: Value) -> Result<Value> {
+    if let Value::Map(map) = arr {
+        let key = HashKey::try_from(num)?;
+        return Ok(map.borrow().get(&key).cloned().unwrap_or(Value::Nil));
+    }
+
+    if let Value::Range {
+        start,
+        end,
+        inclusive,
+    } = num
+    {
+        return eval_slice(arr, start, end, inclusive);
+    }
+
+    let index = match num {
+        Value::Int(n) => n,
+        value => {
+            return Err(Error::type_mismatch(
+                "INDEX",
+                format!("'{value}' not an integer"),
+            ))
+        }
+    };
+
+    match arr {
+        Value::Array(arr) => {
+            let arr = arr.borrow();
+            let index = resolve_index(index, arr.len())?;
+            Ok(arr[index].clone())
+        }
+        Value::String(str) => {
+            let index = resolve_index(index, str.chars().count())?;
+            let retorno = str.chars().nth(index).unwrap().to_string();
+
+            Ok(Value::String(retorno.into()))
+        }
+        value => Err(Error::type_mismatch(
+            "INDEX",
+            format!("'{value}' not iterable"),
+        )),
+    }
+}
+
+pub(crate) fn eval_slice(
+    arr: Value,
+    start: Option<i64>,
+    end: Option<i64>,
+    inclusive: bool,
+) -> Result<Value> {
+    match arr {
+        Value::Array(arr) => {
+            let arr = arr.borrow();
+            let (start, end) = slice_bounds(start, end, inclusive, arr.len());
+            let sliced = arr[start..end].to_vec();
+            Ok(Value::Array(Rc::new(RefCell::new(sliced))))
+        }
+        Value::String(str) => {
+            let (start, end) = slice_bounds(start, end, inclusive, str.chars().count());
+            let sliced: String = str.chars().skip(start).take(end - start).collect();
+            Ok(Value::String(sliced.into()))
+        }
+        value => Err(Error::type_mismatch(
+            "INDEX",
+            format!("'{value}' not sliceable"),
+        )),
+    }
+}
+
+pub(crate) fn eval_range(op: Op, start: Value, end: Value) -> Result<Value> {
+    let bound = |value: Value| match value {
+        Value::Nil => Ok(None),
+        Value::Int(num) => Ok(Some(num)),
+        value => Err(Error::type_mismatch(
+            op,
+            format!("'{value}' expected integer"),
+        )),
+    };
+
+    return Ok(Value::Range {
+        start: bound(start)?,
+        end: bound(end)?,
+        inclusive: op == Op::RangeInclusive,
+    });
+}
+
+pub(crate) fn assign_index(container: Value, key: Value, value: Value) -> Result<Value> {
+    match container {
+        Value::Array(arr) => {
+            let index = match key {
+                Value::Int(n) => n,
+                value => {
+                    return Err(Error::type_mismatch(
+                        "INDEX",
+                        format!("'{value}' not an integer"),
+                    ))
+                }
+            };
+            let mut arr = arr.borrow_mut();
+            let index = resolve_index(index, arr.len())?;
+            arr[index] = value;
+        }
+        Value::Map(map) => {
+            let key = HashKey::try_from(key)?;
+            map.borrow_mut().insert(key, value);
+        }
+        Value::String(_) => {
+            return Err(Error::runtime(
+                "INDEX",
+                "strings are immutable, build a new one instead",
+            ))
+        }
+        value => {
+            return Err(Error::type_mismatch(
+                "INDEX",
+                format!("'{value}' not indexable"),
+            ))
+        }
+    }
+
+    return Ok(Value::Idle);
+}
+
+/// Combines the current value of a place with the one assigned to it by a
+/// compound operator like `+=`, plain `=` simply replaces it.
+pub(crate) fn eval_compound(op: Op, current: Value, value: Value) -> Result<Value> {
+    match op {
+        Op::PlusAssign => eval_plus(current, value),
+        Op::MinusAssign => eval_infix_numbers(Op::Minus, current, value),
+        Op::StarAssign => eval_infix_numbers(Op::Star, current, value),
+        Op::SlashAssign => eval_infix_numbers(Op::Slash, current, value),
+        _ => Ok(value),
+    }
+}
+
+/// `-` and `!` applied to an already evaluated operand.
+pub(crate) fn eval_prefix(op: Op, val: Value) -> Result<Value> {
+    match (op, val) {
+        (Op::Minus, Value::Int(num)) => match num.checked_neg() {
+            Some(negated) => Ok(Value::Int(negated)),
+            None => Err(Error::arithmetic(
+                ArithmeticError::Overflow,
+                format!("'{op}' {num}"),
+            )),
+        },
+        (Op::Minus, Value::Float(num)) => Ok(Value::Float(-num)),
+        (Op::Bang, Value::Bool(val)) => Ok(Value::Bool(!val)),
+        (Op::Bang, Value::Nil) => Ok(Value::Bool(true)),
+        (op, value) => Err(Error::type_mismatch(op, format!("'{value}'"))),
+    }
+}
@@ -1,17 +1,25 @@
-use anyhow::{anyhow, Result};
 use core::fmt;
 use std::rc::Rc;
 
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+use crate::error::{Error, Result};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Comma,
     Dot,
+    DotDot,
+    DotDotEqual,
     Minus,
     Plus,
     Slash,
     Colon,
     Semicolon,
     Star,
+    StarStar,
+    Percent,
+    SlashSlash,
     LParen,
     RParen,
     LBrace,
@@ -27,13 +35,18 @@ pub enum Token {
     LessEqual,
     Assign,
     AssignEqual,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
 
     Ident(Rc<str>),
     String(Rc<str>),
-    Number(Rc<str>, f64),
+    Template(Rc<[StringPart]>),
+    Int(Rc<str>, i64),
+    Float(Rc<str>, f64),
 
     And,
-    Print,
     Else,
     Return,
     True,
@@ -41,13 +54,13 @@ pub enum Token {
     Or,
     Fn,
     False,
-    Len,
     If,
     Nil,
-    Push,
-    First,
-    Last,
-    Rest,
+    While,
+    Break,
+    Continue,
+    For,
+    In,
 
     EOF,
 }
@@ -66,9 +79,14 @@ impl fmt::Display for Token {
                 Token::RBracket => "RIGHT_BRACKET ]",
 
                 Token::Dot => "DOT .",
+                Token::DotDot => "DOT_DOT ..",
+                Token::DotDotEqual => "DOT_DOT_EQUAL ..=",
                 Token::Minus => "MINUS -",
                 Token::Plus => "PLUS +",
                 Token::Star => "STAR *",
+                Token::StarStar => "STAR_STAR **",
+                Token::Percent => "PERCENT %",
+                Token::SlashSlash => "SLASH_SLASH //",
                 Token::Semicolon => "SEMICOLON ;",
                 Token::Colon => "COLON :",
                 Token::Comma => "COMMA ,",
@@ -76,6 +94,10 @@ impl fmt::Display for Token {
                 Token::BangEqual => "BANG_EQUAL !=",
                 Token::Assign => "EQUAL =",
                 Token::AssignEqual => "EQUAL_EQUAL ==",
+                Token::PlusEqual => "PLUS_EQUAL +=",
+                Token::MinusEqual => "MINUS_EQUAL -=",
+                Token::StarEqual => "STAR_EQUAL *=",
+                Token::SlashEqual => "SLASH_EQUAL /=",
                 Token::Greater => "GREATER >",
                 Token::GreaterEqual => "GREATER_EQUAL >=",
                 Token::Less => "LESS <",
@@ -88,26 +110,27 @@ impl fmt::Display for Token {
                 Token::True => "TRUE true",
                 Token::If => "IF if",
                 Token::Else => "ELSE else",
-                Token::Print => "PRINT print",
                 Token::Let => "LET let",
                 Token::Fn => "FN fun",
                 Token::Nil => "NIL nil",
-                Token::Len => "LEN len",
                 Token::Return => "RETURN return",
-                Token::Push => "PUSH push",
-                Token::First => "FIRST first",
-                Token::Last => "LAST last",
-                Token::Rest => "REST rest",
+                Token::While => "WHILE while",
+                Token::Break => "BREAK break",
+                Token::Continue => "CONTINUE continue",
+                Token::For => "FOR for",
+                Token::In => "IN in",
 
                 Token::Ident(val) => return write!(f, "IDENTIFIER {val}"),
                 Token::String(val) => return write!(f, "STRING \"{val}\" {val}"),
-                Token::Number(lit, num) => {
-                    if *num == num.trunc() {
-                        return write!(f, "NUMBER {lit} {:.1}", num);
-                    } else {
-                        return write!(f, "NUMBER {lit} {num}");
+                Token::Template(parts) => {
+                    write!(f, "TEMPLATE \"")?;
+                    for part in parts.iter() {
+                        write!(f, "{part}")?;
                     }
+                    return write!(f, "\"");
                 }
+                Token::Int(lit, num) => return write!(f, "NUMBER {lit} {num}"),
+                Token::Float(lit, num) => return write!(f, "NUMBER {lit} {num:?}"),
 
                 Token::EOF => "EOF ",
             }
@@ -115,28 +138,93 @@ impl fmt::Display for Token {
     }
 }
 
+/// A piece of an interpolated string: either text or the source of a `${...}`
+/// expression together with where it sits in the surrounding source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Literal(Rc<str>),
+    Expr { source: Rc<str>, span: Span },
+}
+
+impl fmt::Display for StringPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StringPart::Literal(str) => write!(f, "{str}"),
+            StringPart::Expr { source, .. } => write!(f, "${{{source}}}"),
+        }
+    }
+}
+
+/// A region of the source: the byte `offset` where it starts, its length in
+/// bytes, and the `line` and `column` of its first char, both starting at 1.
+/// Columns count chars, so they match what an editor shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(offset: usize, len: usize, line: usize, column: usize) -> Self {
+        return Self {
+            offset,
+            len,
+            line,
+            column,
+        };
+    }
+
+    /// The span from the start of `self` to the end of `end`.
+    pub fn to(self, end: Span) -> Span {
+        return Span {
+            len: (end.offset + end.len).saturating_sub(self.offset),
+            ..self
+        };
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TokenKind {
     pub token: Token,
-    pub line: usize,
+    pub span: Span,
+    /// The `///` comments right before the token, one line each, without
+    /// the slashes.
+    pub doc: Option<Rc<str>>,
 }
 
 pub struct Lexer {
     input: String,
     char: char,
+    /// Where `input` starts in the source it was taken from.
+    offset: usize,
     line: usize,
+    column: usize,
     current_pos: usize,
     next_pos: usize,
+    /// Doc comment lines waiting for the token they document.
+    doc: Vec<String>,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
+        return Self::with_span(input, Span::new(0, 0, 1, 1));
+    }
+
+    /// Lexes `input` as if it was found at `span` of a bigger source, used
+    /// for source embedded in other tokens such as string interpolations.
+    pub fn with_span(input: String, span: Span) -> Self {
         let mut lexer = Self {
             input,
             char: '\0',
-            line: 1,
+            offset: span.offset,
+            line: span.line,
+            // moving onto the first char below advances the column
+            column: span.column.saturating_sub(1),
             current_pos: 0,
             next_pos: 0,
+            doc: Vec::new(),
         };
 
         lexer.next_char();
@@ -144,35 +232,63 @@ impl Lexer {
     }
 
     fn next_char(&mut self) {
-        if self.next_pos >= self.input.len() {
-            self.current_pos = self.next_pos;
-            self.next_pos += 1;
-            self.char = '\0';
-            return;
+        if self.char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
         }
 
         self.current_pos = self.next_pos;
-        self.next_pos += 1;
-        self.char = self.input.as_bytes()[self.current_pos] as char;
-
-        if self.char == '\n' && self.current_pos != self.input.len() - 1 {
-            self.line += 1;
+        match self.char_at(self.current_pos) {
+            Some(ch) => {
+                self.char = ch;
+                self.next_pos += ch.len_utf8();
+            }
+            None => {
+                self.char = '\0';
+                self.next_pos += 1;
+            }
         }
     }
 
-    fn peek(&mut self) -> char {
-        if self.next_pos >= self.input.len() {
-            return '\0';
-        }
+    /// The char starting at byte `pos`, positions are always kept on char
+    /// boundaries.
+    fn char_at(&self, pos: usize) -> Option<char> {
+        return self.input.get(pos..)?.chars().next();
+    }
+
+    /// An empty span at the current char.
+    fn here(&self) -> Span {
+        return Span::new(self.offset + self.current_pos, 0, self.line, self.column);
+    }
+
+    /// The span from `start` up to and including the current char.
+    fn span_from(&self, start: Span) -> Span {
+        let end = self.offset + self.next_pos.min(self.input.len());
+        return start.to(Span::new(end, 0, self.line, self.column));
+    }
+
+    fn peek(&self) -> char {
+        return self.char_at(self.next_pos).unwrap_or('\0');
+    }
 
-        return self.input.as_bytes()[self.next_pos] as char;
+    fn peek_next(&self) -> char {
+        let next = self.next_pos + self.peek().len_utf8();
+        return self.char_at(next).unwrap_or('\0');
     }
 
-    fn skip_whitespace(&mut self) {
+    fn skip_whitespace(&mut self) -> Result<()> {
         loop {
-            if self.char == '/' && self.peek() == '/' {
-                self.next_char();
-                self.skip_comments();
+            if self.char == '#'
+                || (self.char == '/' && self.peek() == '/' && self.peek_next() == '/')
+            {
+                self.skip_comment();
+            }
+
+            if self.char == '/' && self.peek() == '*' {
+                self.skip_block_comment()?;
+                continue;
             }
 
             if !self.char.is_whitespace() {
@@ -181,15 +297,61 @@ impl Lexer {
 
             self.next_char();
         }
+
+        return Ok(());
     }
 
-    fn skip_comments(&mut self) {
+    /// Skips a line comment starting at the current char, a `#` or three or
+    /// more slashes. Lines starting with exactly three slashes are kept as doc
+    /// comments.
+    fn skip_comment(&mut self) {
+        let start_pos = self.current_pos + "///".len();
+        let is_doc = self.char == '/' && self.char_at(start_pos) != Some('/');
+
         loop {
             self.next_char();
             if self.char == '\n' || self.char == '\0' {
                 break;
             }
         }
+
+        if is_doc {
+            let line = &self.input[start_pos..self.current_pos.min(self.input.len())];
+            let line = line.strip_prefix(' ').unwrap_or(line);
+            self.doc.push(line.trim_end().to_string());
+        }
+    }
+
+    /// Skips a `/* ... */` comment starting at the current char, comments
+    /// nested inside it have to be closed too.
+    fn skip_block_comment(&mut self) -> Result<()> {
+        let start = self.here();
+        let mut depth = 0;
+
+        loop {
+            match (self.char, self.peek()) {
+                ('\0', _) => {
+                    return Err(Error::lexical(
+                        "Unterminated block comment, expected '*/'",
+                        self.span_from(start),
+                    ))
+                }
+                ('/', '*') => {
+                    depth += 1;
+                    self.next_char();
+                }
+                ('*', '/') => {
+                    depth -= 1;
+                    self.next_char();
+                    if depth == 0 {
+                        self.next_char();
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.next_char();
+        }
     }
 
     fn is_post_equal(&mut self, yes: Token, no: Token) -> Token {
@@ -202,55 +364,303 @@ impl Lexer {
         return no;
     }
 
-    fn read_string(&mut self) -> Option<Result<TokenKind>> {
-        let start_pos = self.current_pos + 1;
-        while self.peek() != '"' && self.peek() != '\0' {
-            self.next_char();
+    /// Reads a string literal starting at the opening quote and stops on the
+    /// closing one. Escapes are decoded while copying, and `${...}` splits the
+    /// literal into parts whose source is handed to the parser untouched.
+    fn read_string(&mut self) -> Result<Token> {
+        let start = self.here();
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut segment = self.current_pos + 1;
+        let mut error = None;
+
+        loop {
+            match self.peek() {
+                '\0' => {
+                    return Err(Error::lexical(
+                        "Unterminated string.",
+                        self.span_from(start),
+                    ))
+                }
+                '"' => {
+                    literal.push_str(&self.input[segment..self.next_pos]);
+                    self.next_char();
+                    break;
+                }
+                '\\' => {
+                    literal.push_str(&self.input[segment..self.next_pos]);
+                    self.next_char();
+                    match self.read_escape() {
+                        Ok(ch) => literal.push(ch),
+                        Err(err) => {
+                            error.get_or_insert(err);
+                        }
+                    }
+                    segment = self.next_pos;
+                }
+                '$' if self.peek_next() == '{' => {
+                    literal.push_str(&self.input[segment..self.next_pos]);
+                    if !literal.is_empty() {
+                        parts.push(StringPart::Literal(std::mem::take(&mut literal).into()));
+                    }
+                    self.next_char(); // consume '$'
+                    self.next_char(); // consume '{'
+                    parts.push(self.read_interpolation()?);
+                    segment = self.next_pos;
+                }
+                _ => self.next_char(),
+            }
         }
 
-        if self.peek() != '"' {
-            let msg = Err(anyhow::Error::msg(format!(
-                "[line: {}] Error: Unterminated string.",
-                self.line
-            )));
+        if let Some(err) = error {
+            return Err(err);
+        }
 
-            return Some(msg);
+        if parts.is_empty() {
+            return Ok(Token::String(literal.into()));
+        }
+        if !literal.is_empty() {
+            parts.push(StringPart::Literal(literal.into()));
         }
 
-        if start_pos == self.current_pos + 1 {
-            let str: Rc<str> = self.input[start_pos..start_pos].into();
-            return Some(Ok(TokenKind {
-                token: Token::String(str),
-                line: self.line,
-            }));
+        return Ok(Token::Template(parts.into()));
+    }
+
+    /// Decodes the escape sequence whose backslash is the current char.
+    fn read_escape(&mut self) -> Result<char> {
+        let start = self.here();
+        self.next_char();
+        let escaped = match self.char {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            '$' => '$',
+            'u' => {
+                if self.peek() != '{' {
+                    return Err(Error::lexical(
+                        "Invalid unicode escape, expected '{' after \\u",
+                        self.span_from(start),
+                    ));
+                }
+                self.next_char();
+
+                let start_pos = self.next_pos;
+                while self.peek().is_ascii_hexdigit() {
+                    self.next_char();
+                }
+                let digits = self.input[start_pos..self.next_pos].to_string();
+                if self.peek() != '}' || digits.is_empty() || digits.len() > 6 {
+                    return Err(Error::lexical(
+                        format!("Invalid unicode escape '\\u{{{digits}'"),
+                        self.span_from(start),
+                    ));
+                }
+                self.next_char();
+
+                match u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    Some(ch) => ch,
+                    None => {
+                        return Err(Error::lexical(
+                            format!("Invalid unicode code point '\\u{{{digits}}}'"),
+                            self.span_from(start),
+                        ))
+                    }
+                }
+            }
+            '\0' => {
+                return Err(Error::lexical(
+                    "Unterminated string.",
+                    self.span_from(start),
+                ))
+            }
+            ch => {
+                return Err(Error::lexical(
+                    format!("Invalid escape sequence '\\{ch}'"),
+                    self.span_from(start),
+                ))
+            }
+        };
+
+        return Ok(escaped);
+    }
+
+    /// Collects the source of a `${...}` interpolation up to its matching
+    /// brace, skipping over nested braces and string literals.
+    fn read_interpolation(&mut self) -> Result<StringPart> {
+        // the source starts right after the current '{'
+        let here = self.here();
+        let start = Span::new(here.offset + 1, 0, here.line, here.column + 1);
+        let start_pos = self.next_pos;
+        let mut depth = 0;
+
+        loop {
+            match self.peek() {
+                '\0' => {
+                    return Err(Error::lexical(
+                        "Unterminated interpolation, expected '}'",
+                        self.span_from(start),
+                    ))
+                }
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                '"' => {
+                    self.next_char();
+                    while self.peek() != '"' {
+                        match self.peek() {
+                            '\0' => {
+                                return Err(Error::lexical(
+                                    "Unterminated interpolation, expected '}'",
+                                    self.span_from(start),
+                                ))
+                            }
+                            '\\' => self.next_char(),
+                            _ => {}
+                        }
+                        self.next_char();
+                    }
+                }
+                _ => {}
+            }
+            self.next_char();
         }
 
-        let str: Rc<str> = self.input[start_pos..=self.current_pos].into();
-        return Some(Ok(TokenKind {
-            token: Token::String(str),
-            line: self.line,
-        }));
+        let source: Rc<str> = self.input[start_pos..self.next_pos].into();
+        self.next_char(); // consume '}'
+
+        let span = Span {
+            len: source.len(),
+            ..start
+        };
+
+        return Ok(StringPart::Expr { source, span });
     }
 
-    fn read_number(&mut self) -> Rc<str> {
+    /// Reads a number starting at the current digit: a decimal one with an
+    /// optional fraction and exponent, or a `0x`, `0o` or `0b` integer. Two
+    /// digits can be separated with `_`, which is kept in the token's literal.
+    fn read_number(&mut self) -> Result<Token> {
+        let start = self.here();
         let start_pos = self.current_pos;
 
-        while self.peek().is_ascii_digit() {
+        let radix = match (self.char, self.peek()) {
+            ('0', 'x') => Some((16, "hex")),
+            ('0', 'o') => Some((8, "octal")),
+            ('0', 'b') => Some((2, "binary")),
+            _ => None,
+        };
+        if let Some((radix, kind)) = radix {
             self.next_char();
+            // letters are read too, so `0b12` is one bad literal, not two tokens
+            while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+                self.next_char();
+            }
+
+            let literal: Rc<str> = self.input[start_pos..self.next_pos].into();
+            let digits = literal[2..].replace('_', "");
+            if digits.is_empty() {
+                return Err(Error::lexical(
+                    format!("Invalid {kind} literal {literal}, expected digits after the prefix"),
+                    self.span_from(start),
+                ));
+            }
+            if let Some(digit) = digits.chars().find(|ch| !ch.is_digit(radix)) {
+                return Err(Error::lexical(
+                    format!("Invalid digit '{digit}' in {kind} literal {literal}"),
+                    self.span_from(start),
+                ));
+            }
+            if has_stray_separator(&literal[2..], radix) {
+                return Err(self.stray_separator(&literal, start));
+            }
+
+            return match i64::from_str_radix(&digits, radix) {
+                Ok(number) => Ok(Token::Int(literal, number)),
+                Err(_) => Err(Error::lexical(
+                    format!("Integer literal {literal} is too large"),
+                    self.span_from(start),
+                )),
+            };
         }
 
-        if self.peek() == '.' {
+        self.read_digits();
+        let mut is_float = false;
+
+        // `1..5` is a range, not the number `1.` followed by `.5`
+        if self.peek() == '.' && self.peek_next() != '.' {
             self.next_char();
+            is_float = true;
             if self.peek().is_ascii_digit() {
-                while self.peek().is_ascii_digit() {
-                    self.next_char();
-                }
+                self.read_digits();
             }
+        }
+
+        if matches!(self.peek(), 'e' | 'E') && self.char != '.' {
+            self.next_char();
+            is_float = true;
+            if matches!(self.peek(), '+' | '-') {
+                self.next_char();
+            }
+            if !self.peek().is_ascii_digit() {
+                return Err(Error::lexical(
+                    format!(
+                        "Invalid number literal {}, expected digits in the exponent",
+                        &self.input[start_pos..self.next_pos]
+                    ),
+                    self.span_from(start),
+                ));
+            }
+            self.read_digits();
+        }
+
+        let literal: Rc<str> = self.input[start_pos..self.next_pos].into();
+        if has_stray_separator(&literal, 10) {
+            return Err(self.stray_separator(&literal, start));
+        }
 
-            return self.input[start_pos..=self.current_pos].into();
+        let digits = literal.replace('_', "");
+        if is_float {
+            return match digits.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(Token::Float(literal, number)),
+                Ok(_) => Err(Error::lexical(
+                    format!("Float literal {literal} is too large"),
+                    self.span_from(start),
+                )),
+                Err(_) => Err(Error::lexical(
+                    format!("Invalid number literal {literal}"),
+                    self.span_from(start),
+                )),
+            };
         }
 
-        return self.input[start_pos..=self.current_pos].into();
+        return match digits.parse::<i64>() {
+            Ok(number) => Ok(Token::Int(literal, number)),
+            Err(_) => Err(Error::lexical(
+                format!("Integer literal {literal} is too large"),
+                self.span_from(start),
+            )),
+        };
+    }
+
+    /// Consumes the digits following the current char, along with the `_`
+    /// separating them.
+    fn read_digits(&mut self) {
+        while self.peek().is_ascii_digit() || self.peek() == '_' {
+            self.next_char();
+        }
+    }
+
+    fn stray_separator(&self, literal: &str, start: Span) -> Error {
+        return Error::lexical(
+            format!("Invalid number literal {literal}, '_' can only separate two digits"),
+            self.span_from(start),
+        );
     }
 
     fn read_ident(&self, literal: &str) -> Token {
@@ -265,12 +675,11 @@ impl Lexer {
             "true" => Token::True,
             "false" => Token::False,
             "nil" => Token::Nil,
-            "print" => Token::Print,
-            "len" => Token::Len,
-            "push" => Token::Push,
-            "first" => Token::First,
-            "last" => Token::Last,
-            "rest" => Token::Rest,
+            "while" => Token::While,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
+            "for" => Token::For,
+            "in" => Token::In,
             _ => Token::Ident(literal.into()),
         };
     }
@@ -284,7 +693,17 @@ impl Iterator for Lexer {
             return None;
         }
 
-        self.skip_whitespace();
+        if let Err(err) = self.skip_whitespace() {
+            self.doc.clear();
+            return Some(Err(err));
+        }
+        let start = self.here();
+        let doc = if self.doc.is_empty() {
+            None
+        } else {
+            Some(self.doc.join("\n").into())
+        };
+        self.doc.clear();
 
         let token = match self.char {
             '{' => Token::LBrace,
@@ -294,70 +713,131 @@ impl Iterator for Lexer {
             '[' => Token::LBracket,
             ']' => Token::RBracket,
 
-            '.' => Token::Dot,
+            '.' => {
+                if self.peek() == '.' {
+                    self.next_char();
+                    self.is_post_equal(Token::DotDotEqual, Token::DotDot)
+                } else {
+                    Token::Dot
+                }
+            }
             ',' => Token::Comma,
-            '-' => Token::Minus,
-            '*' => Token::Star,
+            '-' => self.is_post_equal(Token::MinusEqual, Token::Minus),
+            '*' => {
+                if self.peek() == '*' {
+                    self.next_char();
+                    Token::StarStar
+                } else {
+                    self.is_post_equal(Token::StarEqual, Token::Star)
+                }
+            }
+            '%' => Token::Percent,
             ';' => Token::Semicolon,
             ':' => Token::Colon,
-            '+' => Token::Plus,
-            '/' => Token::Slash,
+            '+' => self.is_post_equal(Token::PlusEqual, Token::Plus),
+            '/' => {
+                if self.peek() == '/' {
+                    self.next_char();
+                    Token::SlashSlash
+                } else {
+                    self.is_post_equal(Token::SlashEqual, Token::Slash)
+                }
+            }
             '!' => self.is_post_equal(Token::BangEqual, Token::Bang),
             '=' => self.is_post_equal(Token::AssignEqual, Token::Assign),
             '>' => self.is_post_equal(Token::GreaterEqual, Token::Greater),
             '<' => self.is_post_equal(Token::LessEqual, Token::Less),
 
-            '"' => {
-                let item = self.read_string();
-                self.next_char(); // consume '"'
-                self.next_char(); // consume the next after '"'
-                return item;
-            }
-
-            '0'..='9' => {
-                let literal = self.read_number();
-                let number = literal.parse::<f64>().unwrap();
+            '"' => match self.read_string() {
+                Ok(token) => token,
+                Err(err) => {
+                    self.next_char();
+                    return Some(Err(err));
+                }
+            },
 
-                Token::Number(literal, number)
-            }
-            'a'..='z' | '_' | 'A'..='Z' => {
+            '0'..='9' => match self.read_number() {
+                Ok(token) => token,
+                Err(err) => {
+                    self.next_char();
+                    return Some(Err(err));
+                }
+            },
+            ch if ch == '_' || is_xid_start(ch) => {
                 let start_pos = self.current_pos;
-                while self.peek().is_alphanumeric() || self.peek() == '_' {
+                while is_xid_continue(self.peek()) {
                     self.next_char();
                 }
-                let tok = self.read_ident(&self.input[start_pos..=self.current_pos]);
-
-                tok
+                self.read_ident(&self.input[start_pos..self.next_pos])
             }
 
             '\0' => Token::EOF,
 
             _ => {
-                let error = Some(Err(anyhow!(
-                    "[line: {}] Error: Unexpected character: {}",
-                    self.line,
-                    self.char
+                let error = Some(Err(Error::lexical(
+                    format!("Unexpected character: {}", self.char),
+                    self.span_from(start),
                 )));
                 self.next_char();
                 return error;
             }
         };
 
+        let span = self.span_from(start);
         self.next_char();
 
-        return Some(Ok(TokenKind {
-            token,
-            line: self.line,
-        }));
+        return Some(Ok(TokenKind { token, span, doc }));
     }
 }
 
+/// Whether a `_` in `digits` isn't between two digits, like in `1_` or `1__0`.
+fn has_stray_separator(digits: &str, radix: u32) -> bool {
+    let chars: Vec<char> = digits.chars().collect();
+    return chars.iter().enumerate().any(|(i, &ch)| {
+        let is_digit = |pos: Option<usize>| {
+            pos.and_then(|pos| chars.get(pos))
+                .is_some_and(|ch| ch.is_digit(radix))
+        };
+        ch == '_' && !(is_digit(i.checked_sub(1)) && is_digit(Some(i + 1)))
+    });
+}
+
 #[cfg(test)]
 mod test {
 
-    use anyhow::Result;
+    use std::rc::Rc;
 
-    use super::{Lexer, Token};
+    use crate::error::{Error, Result};
+
+    use super::{Lexer, Span, StringPart, Token};
+
+    #[test]
+    fn test_spans() -> Result<()> {
+        let input = "let answer = 42;\n  print(answer);".to_string();
+
+        let expected = [
+            (Token::Let, Span::new(0, 3, 1, 1)),
+            (Token::Ident("answer".into()), Span::new(4, 6, 1, 5)),
+            (Token::Assign, Span::new(11, 1, 1, 12)),
+            (Token::Int("42".into(), 42), Span::new(13, 2, 1, 14)),
+            (Token::Semicolon, Span::new(15, 1, 1, 16)),
+            (Token::Ident("print".into()), Span::new(19, 5, 2, 3)),
+            (Token::LParen, Span::new(24, 1, 2, 8)),
+            (Token::Ident("answer".into()), Span::new(25, 6, 2, 9)),
+            (Token::RParen, Span::new(31, 1, 2, 15)),
+            (Token::Semicolon, Span::new(32, 1, 2, 16)),
+            (Token::EOF, Span::new(33, 0, 2, 17)),
+        ];
+
+        let tokens = Lexer::new(input).collect::<Result<Vec<_>>>()?;
+        assert_eq!(tokens.len(), expected.len());
+
+        for (tok, (token, span)) in tokens.into_iter().zip(expected) {
+            assert_eq!((token, span), (tok.token, tok.span));
+        }
+
+        Ok(())
+    }
 
     #[test]
     fn test_numbers() -> Result<()> {
@@ -367,12 +847,12 @@ mod test {
         123."#
             .to_string();
 
-        let expected = vec![
-            Token::Number("123".into(), 123.0),
-            Token::Number("123.456".into(), 123.456),
+        let expected = [
+            Token::Int("123".into(), 123),
+            Token::Float("123.456".into(), 123.456),
             Token::Dot,
-            Token::Number("456".into(), 456.0),
-            Token::Number("123.".into(), 123.0),
+            Token::Int("456".into(), 456),
+            Token::Float("123.".into(), 123.0),
             Token::EOF,
         ];
 
@@ -394,13 +874,143 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_number_literals() -> Result<()> {
+        let input = "0xFF 0b1010 0o777 1_000_000 6.02e23 1E-3 2.5e+2_0 0xf_f_ff 1.e 0x 1e 1e+ 0b102 0o8 0x8000000000000000 7"
+            .to_string();
+
+        let expected = [
+            Ok(Token::Int("0xFF".into(), 255)),
+            Ok(Token::Int("0b1010".into(), 10)),
+            Ok(Token::Int("0o777".into(), 511)),
+            Ok(Token::Int("1_000_000".into(), 1_000_000)),
+            Ok(Token::Float("6.02e23".into(), 6.02e23)),
+            Ok(Token::Float("1E-3".into(), 1e-3)),
+            Ok(Token::Float("2.5e+2_0".into(), 2.5e20)),
+            Ok(Token::Int("0xf_f_ff".into(), 0xffff)),
+            // the exponent needs a digit before it, here `e` is a name
+            Ok(Token::Float("1.".into(), 1.0)),
+            Ok(Token::Ident("e".into())),
+            Err(Error::lexical(
+                "Invalid hex literal 0x, expected digits after the prefix",
+                Span::new(63, 2, 1, 64),
+            )),
+            Err(Error::lexical(
+                "Invalid number literal 1e, expected digits in the exponent",
+                Span::new(66, 2, 1, 67),
+            )),
+            Err(Error::lexical(
+                "Invalid number literal 1e+, expected digits in the exponent",
+                Span::new(69, 3, 1, 70),
+            )),
+            Err(Error::lexical(
+                "Invalid digit '2' in binary literal 0b102",
+                Span::new(73, 5, 1, 74),
+            )),
+            Err(Error::lexical(
+                "Invalid digit '8' in octal literal 0o8",
+                Span::new(79, 3, 1, 80),
+            )),
+            Err(Error::lexical(
+                "Integer literal 0x8000000000000000 is too large",
+                Span::new(83, 18, 1, 84),
+            )),
+            Ok(Token::Int("7".into(), 7)),
+            Ok(Token::EOF),
+        ];
+
+        let tokens: Vec<Result<Token>> = Lexer::new(input)
+            .map(|tok| tok.map(|tok| tok.token))
+            .collect();
+        assert_eq!(expected.to_vec(), tokens);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_errors() -> Result<()> {
+        let input = "1_ 1__000 0x_ 0x_1 1_.5 1.5_ 1e999 1.7e308".to_string();
+
+        let expected = [
+            Err(Error::lexical(
+                "Invalid number literal 1_, '_' can only separate two digits",
+                Span::new(0, 2, 1, 1),
+            )),
+            Err(Error::lexical(
+                "Invalid number literal 1__000, '_' can only separate two digits",
+                Span::new(3, 6, 1, 4),
+            )),
+            Err(Error::lexical(
+                "Invalid hex literal 0x_, expected digits after the prefix",
+                Span::new(10, 3, 1, 11),
+            )),
+            Err(Error::lexical(
+                "Invalid number literal 0x_1, '_' can only separate two digits",
+                Span::new(14, 4, 1, 15),
+            )),
+            Err(Error::lexical(
+                "Invalid number literal 1_.5, '_' can only separate two digits",
+                Span::new(19, 4, 1, 20),
+            )),
+            Err(Error::lexical(
+                "Invalid number literal 1.5_, '_' can only separate two digits",
+                Span::new(24, 4, 1, 25),
+            )),
+            Err(Error::lexical(
+                "Float literal 1e999 is too large",
+                Span::new(29, 5, 1, 30),
+            )),
+            Ok(Token::Float("1.7e308".into(), 1.7e308)),
+            Ok(Token::EOF),
+        ];
+
+        let tokens: Vec<Result<Token>> = Lexer::new(input)
+            .map(|tok| tok.map(|tok| tok.token))
+            .collect();
+        assert_eq!(expected.to_vec(), tokens);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<()> {
+        let input = "1..3 0..=n".to_string();
+
+        let expected = [
+            Token::Int("1".into(), 1),
+            Token::DotDot,
+            Token::Int("3".into(), 3),
+            Token::Int("0".into(), 0),
+            Token::DotDotEqual,
+            Token::Ident("n".into()),
+            Token::EOF,
+        ];
+
+        let lexer = Lexer::new(input);
+
+        for (i, tok_result) in lexer.enumerate() {
+            let tok = match tok_result {
+                Ok(tok) => tok,
+                Err(err) => panic!("does not expected to error: {}", err),
+            };
+
+            assert_eq!(
+                expected[i], tok.token,
+                "expected: {:?}, got: {:?}",
+                expected[i], tok
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_string() -> Result<()> {
         let input = r#" ""
         "string" "#
             .to_string();
 
-        let expected = vec![
+        let expected = [
             Token::String("".into()),
             Token::String("string".into()),
             Token::EOF,
@@ -424,9 +1034,122 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_string_escapes() -> Result<()> {
+        let input = r#""a\tb\n" "\"q\" \\" "\u{48}\u{1F600}" "\${x}" "é""#.to_string();
+
+        let expected = [
+            Token::String("a\tb\n".into()),
+            Token::String("\"q\" \\".into()),
+            Token::String("H\u{1F600}".into()),
+            Token::String("${x}".into()),
+            Token::String("é".into()),
+            Token::EOF,
+        ];
+
+        let lexer = Lexer::new(input);
+
+        for (i, tok_result) in lexer.enumerate() {
+            let tok = match tok_result {
+                Ok(tok) => tok,
+                Err(err) => panic!("does not expected to error: {}", err),
+            };
+
+            assert_eq!(
+                expected[i], tok.token,
+                "expected: {:?}, got: {:?}",
+                expected[i], tok
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_interpolation() -> Result<()> {
+        let input = "\"sum: ${a + b}!\"\n\"${m[\"}\"]}\"".to_string();
+
+        let expected = [
+            Token::Template(
+                [
+                    StringPart::Literal("sum: ".into()),
+                    StringPart::Expr {
+                        source: "a + b".into(),
+                        span: Span::new(8, 5, 1, 9),
+                    },
+                    StringPart::Literal("!".into()),
+                ]
+                .into(),
+            ),
+            Token::Template(
+                [StringPart::Expr {
+                    source: "m[\"}\"]".into(),
+                    span: Span::new(20, 6, 2, 4),
+                }]
+                .into(),
+            ),
+            Token::EOF,
+        ];
+
+        let lexer = Lexer::new(input);
+
+        for (i, tok_result) in lexer.enumerate() {
+            let tok = match tok_result {
+                Ok(tok) => tok,
+                Err(err) => panic!("does not expected to error: {}", err),
+            };
+
+            assert_eq!(
+                expected[i], tok.token,
+                "expected: {:?}, got: {:?}",
+                expected[i], tok
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_errors() -> Result<()> {
+        let cases = [
+            (
+                "\n\"bad \\q\"",
+                Error::lexical("Invalid escape sequence '\\q'", Span::new(6, 2, 2, 6)),
+            ),
+            (
+                "\"\\u{110000}\"",
+                Error::lexical(
+                    "Invalid unicode code point '\\u{110000}'",
+                    Span::new(1, 10, 1, 2),
+                ),
+            ),
+            (
+                "\"${a\"",
+                Error::lexical(
+                    "Unterminated interpolation, expected '}'",
+                    Span::new(3, 2, 1, 4),
+                ),
+            ),
+            (
+                "\"abc",
+                Error::lexical("Unterminated string.", Span::new(0, 4, 1, 1)),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let err = Lexer::new(input.to_string())
+                .find_map(|tok| tok.err())
+                .expect("expected a lexical error");
+            assert_eq!(expected, err);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_keyword() -> Result<()> {
-        let input = r#"and else false fn if nil or return true let"#.to_string();
+        let input =
+            r#"and else false fn if nil or return true let while break continue print"#.to_string();
 
         let expected = vec![
             Token::And,
@@ -439,6 +1162,10 @@ mod test {
             Token::Return,
             Token::True,
             Token::Let,
+            Token::While,
+            Token::Break,
+            Token::Continue,
+            Token::Ident("print".into()),
             Token::EOF,
         ];
 
@@ -470,7 +1197,7 @@ mod test {
         end"#
             .to_string();
 
-        let expected = vec![
+        let expected = [
             Token::Ident("space".into()),
             Token::Ident("tabs".into()),
             Token::Ident("newlines".into()),
@@ -498,7 +1225,7 @@ mod test {
 
     #[test]
     fn test_punctuation() -> Result<()> {
-        let input = "(){};,+-*!===<=>=!=<>/.".to_string();
+        let input = "(){};,+-*!===<=>=!=<>/.%**// += -= *= /=".to_string();
 
         let expected = vec![
             Token::LParen,
@@ -519,6 +1246,13 @@ mod test {
             Token::Greater,
             Token::Slash,
             Token::Dot,
+            Token::Percent,
+            Token::StarStar,
+            Token::SlashSlash,
+            Token::PlusEqual,
+            Token::MinusEqual,
+            Token::StarEqual,
+            Token::SlashEqual,
             Token::EOF,
         ];
 
@@ -539,4 +1273,92 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_recovery() -> Result<()> {
+        let input = "a @ b $ \"c\"".to_string();
+
+        let expected = [
+            Ok(Token::Ident("a".into())),
+            Err(Error::lexical(
+                "Unexpected character: @",
+                Span::new(2, 1, 1, 3),
+            )),
+            Ok(Token::Ident("b".into())),
+            Err(Error::lexical(
+                "Unexpected character: $",
+                Span::new(6, 1, 1, 7),
+            )),
+            Ok(Token::String("c".into())),
+            Ok(Token::EOF),
+        ];
+
+        let tokens: Vec<Result<Token>> = Lexer::new(input)
+            .map(|tok| tok.map(|tok| tok.token))
+            .collect();
+        assert_eq!(expected.to_vec(), tokens);
+
+        Ok(())
+    }
+    #[test]
+    fn test_comments() -> Result<()> {
+        let input = "/// Adds.\n///   two numbers\nfn /* a /* nested */ one */ add # not docs\n//// not docs\nx /* open".to_string();
+
+        let expected = [
+            Ok((Token::Fn, Some("Adds.\n  two numbers".into()))),
+            Ok((Token::Ident("add".into()), None)),
+            Ok((Token::Ident("x".into()), None)),
+            Err(Error::lexical(
+                "Unterminated block comment, expected '*/'",
+                Span::new(87, 7, 5, 3),
+            )),
+            Ok((Token::EOF, None)),
+        ];
+
+        let tokens: Vec<Result<(Token, Option<Rc<str>>)>> = Lexer::new(input)
+            .map(|tok| tok.map(|tok| (tok.token, tok.doc)))
+            .collect();
+        assert_eq!(expected.to_vec(), tokens);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode() -> Result<()> {
+        let input = "let café = \"naïve 😀 ${名前}\";\n😀 _ñ2".to_string();
+
+        let expected = [
+            Ok((Token::Let, Span::new(0, 3, 1, 1))),
+            Ok((Token::Ident("café".into()), Span::new(4, 5, 1, 5))),
+            Ok((Token::Assign, Span::new(10, 1, 1, 10))),
+            Ok((
+                Token::Template(
+                    [
+                        StringPart::Literal("naïve 😀 ".into()),
+                        StringPart::Expr {
+                            source: "名前".into(),
+                            span: Span::new(27, 6, 1, 23),
+                        },
+                    ]
+                    .into(),
+                ),
+                Span::new(12, 23, 1, 12),
+            )),
+            Ok((Token::Semicolon, Span::new(35, 1, 1, 27))),
+            // emoji aren't identifier chars, but the error covers all of it
+            Err(Error::lexical(
+                "Unexpected character: 😀",
+                Span::new(37, 4, 2, 1),
+            )),
+            Ok((Token::Ident("_ñ2".into()), Span::new(42, 4, 2, 3))),
+            Ok((Token::EOF, Span::new(46, 0, 2, 6))),
+        ];
+
+        let tokens: Vec<Result<(Token, Span)>> = Lexer::new(input)
+            .map(|tok| tok.map(|tok| (tok.token, tok.span)))
+            .collect();
+        assert_eq!(expected.to_vec(), tokens);
+
+        Ok(())
+    }
 }
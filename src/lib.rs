@@ -1,5 +1,10 @@
+pub mod compiler;
+pub mod diagnostic;
+pub mod error;
 pub mod eval;
 pub mod lexer;
 pub mod parser;
 pub mod repl;
+pub mod resolver;
+pub mod vm;
 mod wasm;
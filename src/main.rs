@@ -1,10 +1,13 @@
 use std::{env, fs, io, process};
 
 use monkelang::{
+    diagnostic::Diagnostic,
+    error::Error,
     eval::{Env, Evaluator},
     lexer::Lexer,
     parser::Parser,
     repl,
+    vm::Vm,
 };
 
 fn main() {
@@ -33,15 +36,14 @@ fn main() {
                 String::new()
             });
 
-            let lexer = Lexer::new(file_contents);
+            let lexer = Lexer::new(file_contents.clone());
             let mut err = 0;
             for token in lexer {
                 match token {
                     Ok(tok) => println!("{}", tok.token),
-                    Err(msg) => {
+                    Err(error) => {
                         err = 65;
-                        eprintln!("{}", msg);
-                        break;
+                        Diagnostic::from(&error).emit(file_path, &file_contents);
                     }
                 };
             }
@@ -59,32 +61,72 @@ fn main() {
                 String::new()
             });
 
-            let mut parser = Parser::new(file_contents);
+            let mut parser = Parser::new(file_contents.clone());
+            let mut code = 0;
             for result in parser.parse() {
                 match result {
                     Ok(ast) => println!("{:?}", ast),
-                    Err(err) => eprintln!("{err}"),
+                    Err(err) => {
+                        code = 65;
+                        Diagnostic::from(&err).emit(file_path, &file_contents);
+                    }
                 }
             }
+
+            process::exit(code);
         }
         "eval" => {
             if args.len() < 3 {
                 eprintln!("Usage: {} <command>", &args[0]);
                 process::exit(1);
             }
+            let vm = match (
+                args.get(3).map(String::as_str),
+                args.get(4).map(String::as_str),
+            ) {
+                (None, _) | (Some("--engine"), Some("tree")) => false,
+                (Some("--engine"), Some("vm")) => true,
+                _ => {
+                    eprintln!("Usage: {} eval <file> [--engine tree|vm]", &args[0]);
+                    process::exit(1);
+                }
+            };
             let file_path = &args[2];
             let file_contents = fs::read_to_string(file_path).unwrap_or_else(|_| {
                 eprintln!("Failed to read file {}", file_path);
                 String::new()
             });
 
-            let mut parser = Parser::new(file_contents);
-            let mut evaluator = Evaluator::new(Env::new(), io::stdout(), io::stderr());
+            let mut parser = Parser::new(file_contents.clone());
 
             let program = parser.parse();
+            let errors: Vec<_> = program
+                .iter()
+                .filter_map(|result| result.as_ref().err())
+                .collect();
+            if !errors.is_empty() {
+                for err in errors {
+                    Diagnostic::from(err).emit(file_path, &file_contents);
+                }
+                process::exit(65);
+            }
 
-            let result = evaluator.eval(program);
-            println!("{result}");
+            let result = if vm {
+                Vm::new(Env::new(), io::stdout()).eval(program)
+            } else {
+                Evaluator::new(Env::new(), io::stdout()).eval(program)
+            };
+            match result {
+                Ok(result) => println!("{result}"),
+                Err(err) => {
+                    Diagnostic::from(&err).emit(file_path, &file_contents);
+                    let code = match err {
+                        Error::Lexical { .. } | Error::Syntax { .. } => 65,
+                        _ => 70,
+                    };
+                    process::exit(code);
+                }
+            }
         }
         _ => eprint!("Unknown command: {}", command),
     }
@@ -1,23 +1,117 @@
 use core::fmt;
 use std::{iter::Peekable, rc::Rc};
 
-use anyhow::{anyhow, Result};
-
-use crate::lexer::{Lexer, Token, TokenKind};
+use crate::{
+    error::{Error, Location, Result},
+    lexer::{Lexer, Span, StringPart, Token, TokenKind},
+};
 
 pub struct Parser {
     lexer: Peekable<Lexer>,
+    /// The span of the last consumed token, where the node being built ends.
+    previous: Span,
+    /// The doc comment of the last consumed token, for the `fn` or `let` it
+    /// starts.
+    doc: Option<Rc<str>>,
+    /// Errors recovered from while parsing the current top level statement,
+    /// reported ahead of it.
+    errors: Vec<Error>,
 }
 
 impl Parser {
     pub fn new(input: String) -> Self {
         return Self {
             lexer: Lexer::new(input).peekable(),
+            previous: Span::default(),
+            doc: None,
+            errors: Vec::new(),
         };
     }
 
+    /// Parses every statement of the input. A statement that fails to parse
+    /// is reported and skipped, so one run finds all the errors of a program.
     pub fn parse(&mut self) -> Vec<Result<AST>> {
-        return self.parse_statement();
+        let mut statements = Vec::new();
+
+        while !self.at_end() {
+            match self.parse_statement() {
+                Ok(ast) => {
+                    statements.extend(self.errors.drain(..).map(Err));
+                    statements.push(Ok(ast));
+                }
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                    statements.extend(self.errors.drain(..).map(Err));
+                }
+            }
+        }
+
+        return statements;
+    }
+
+    /// Skips the rest of a broken statement, up to and including its `;` or
+    /// the block closing it, or up to the next token that starts a statement
+    /// or closes the enclosing block. Lexer errors found on the way are still
+    /// reported.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+
+        while let Some(next) = self.lexer.peek() {
+            match next {
+                Ok(TokenKind { token, .. }) => match token {
+                    Token::EOF => return,
+                    Token::Semicolon if depth == 0 => {
+                        self.advance();
+                        return;
+                    }
+                    Token::RBrace if depth == 0 => return,
+                    Token::RBrace => {
+                        depth -= 1;
+                        self.advance();
+                        if depth == 0 {
+                            return;
+                        }
+                        continue;
+                    }
+                    Token::LBrace => depth += 1,
+                    Token::Fn
+                    | Token::Let
+                    | Token::If
+                    | Token::While
+                    | Token::For
+                    | Token::Return
+                        if depth == 0 =>
+                    {
+                        return
+                    }
+                    _ => {}
+                },
+                Err(err) => self.errors.push(err.clone()),
+            }
+
+            self.advance();
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        return matches!(
+            self.lexer.peek(),
+            None | Some(Ok(TokenKind {
+                token: Token::EOF,
+                ..
+            }))
+        );
+    }
+
+    fn advance(&mut self) -> Option<Result<TokenKind>> {
+        let next = self.lexer.next();
+        if let Some(Ok(token)) = &next {
+            self.previous = token.span;
+            self.doc = token.doc.clone();
+        }
+
+        return next;
     }
 
     fn is_next_token(&mut self, expected: Token) -> bool {
@@ -27,164 +121,199 @@ impl Parser {
         }
     }
 
-    fn expect_peek(&mut self, tok: Token) -> Result<()> {
-        let error = match self.lexer.peek() {
-            Some(Ok(next)) => {
-                if next.token == tok {
-                    self.lexer.next();
-                    return Ok(());
-                }
-                Err(anyhow!("[line: {}] Error: expected {tok}", next.line))
+    /// Where the next token starts, for errors about what's missing there.
+    fn peek_location(&mut self) -> Location {
+        match self.lexer.peek() {
+            Some(Ok(token)) => Location::Span(token.span),
+            _ => Location::EndOfInput,
+        }
+    }
+
+    /// An error for when the next token isn't what `message` says should be
+    /// there. A lexer error in its place is returned instead, and consumed so
+    /// that it's only reported once.
+    fn unexpected(&mut self, message: impl Into<String>) -> Error {
+        if let Some(Err(_)) = self.lexer.peek() {
+            if let Some(Err(err)) = self.advance() {
+                return err;
             }
-            Some(Err(err)) => Err(anyhow!("{err}")),
-            None => return Err(anyhow!("[End of line ] Error: expected {tok}",)),
-        };
+        }
 
-        return error;
+        return Error::syntax(message, self.peek_location());
     }
 
-    fn parse_statement(&mut self) -> Vec<Result<AST>> {
-        let mut statements: Vec<Result<AST>> = Vec::new();
+    fn expect_peek(&mut self, tok: Token) -> Result<()> {
+        if self.is_next_token(tok.clone()) {
+            self.advance();
+            return Ok(());
+        }
 
-        while let Some(tok_result) = self.lexer.peek() {
-            let token_kind = match tok_result {
-                Ok(token) => token,
-                Err(err) => {
-                    statements.push(Err(anyhow!("{err}")));
-                    println!("{:?}", self.lexer.next());
-                    continue;
-                }
-            };
+        return Err(self.unexpected(format!("expected {tok}")));
+    }
 
-            let token = match token_kind.token {
-                Token::Fn => self.parse_fun(),
-                Token::Return => self.parse_return(),
-                Token::If => self.parse_if(),
-                Token::Print => self.parse_print(),
-                Token::Let => self.parse_let(),
-                Token::EOF => break,
+    /// Parses one statement, always consuming at least one token so that
+    /// recovering from an error can't get stuck.
+    fn parse_statement(&mut self) -> Result<AST> {
+        let token = match self.lexer.peek() {
+            Some(Ok(token)) => token.token.clone(),
+            _ => return Err(self.unexpected("expected a statement")),
+        };
+
+        match token {
+            Token::Fn => self.parse_fun(),
+            Token::Return => self.parse_return(),
+            Token::If => self.parse_if(),
+            Token::While => self.parse_while(),
+            Token::For => self.parse_for(),
+            Token::Break => self.parse_loop_control(AST::Break),
+            Token::Continue => self.parse_loop_control(AST::Continue),
+            Token::Let => self.parse_let(),
+
+            Token::LParen
+            | Token::LBracket
+            | Token::LBrace
+            | Token::Int(_, _)
+            | Token::Float(_, _)
+            | Token::String(_)
+            | Token::Template(_)
+            | Token::Ident(_)
+            | Token::Bang
+            | Token::Minus
+            | Token::True
+            | Token::Nil
+            | Token::False => self.parse_expression_statements(),
+
+            token => {
+                let error = Error::syntax(format!("unexpected {token}"), self.peek_location());
+                self.advance();
+                Err(error)
+            }
+        }
+    }
 
-                Token::LParen
+    /// Whether `token` can start an expression. Anything else is reported
+    /// without being consumed, as it likely closes the surrounding construct.
+    fn starts_expression(token: &Token) -> bool {
+        return matches!(
+            token,
+            Token::String(_)
+                | Token::Template(_)
+                | Token::Int(_, _)
+                | Token::Float(_, _)
+                | Token::True
+                | Token::False
+                | Token::Nil
+                | Token::Fn
+                | Token::If
                 | Token::LBracket
-                | Token::Number(_, _)
-                | Token::String(_)
+                | Token::LBrace
                 | Token::Ident(_)
+                | Token::Assign
+                | Token::LParen
+                | Token::DotDot
+                | Token::DotDotEqual
                 | Token::Bang
                 | Token::Minus
-                | Token::True
-                | Token::Len
-                | Token::First
-                | Token::Last
-                | Token::Rest
-                | Token::Push
-                | Token::False => self.parse_expression_statements(),
-                _ => break,
-            };
-
-            statements.push(token);
-        }
-
-        return statements;
+        );
     }
 
     fn parse_expression(&mut self, prev_binding: u8) -> Result<AST> {
-        let l_side = match self.lexer.next() {
+        if let Some(Ok(next)) = self.lexer.peek() {
+            if !Self::starts_expression(&next.token) {
+                return Err(Error::syntax(
+                    "Expected an EXPRESSION",
+                    Location::Span(next.span),
+                ));
+            }
+        }
+
+        let l_side = match self.advance() {
             Some(Ok(tok)) => tok,
             Some(Err(err)) => return Err(err),
-            None => return Ok(AST::Type(Type::Nil)),
+            None => return Ok(AST::Type(Type::Nil, self.previous)),
         };
+        let start = l_side.span;
 
         let mut to_return = match l_side.token {
-            Token::String(val) => AST::Type(Type::String(val)),
-            Token::Number(_, num) => AST::Type(Type::Number(num)),
-            Token::True => AST::Type(Type::Bool(true)),
-            Token::False => AST::Type(Type::Bool(false)),
+            Token::String(val) => AST::Type(Type::String(val), start),
+            Token::Template(parts) => self.parse_template(parts, start)?,
+            Token::Int(_, num) => AST::Type(Type::Int(num), start),
+            Token::Float(_, num) => AST::Type(Type::Float(num), start),
+            Token::True => AST::Type(Type::Bool(true), start),
+            Token::False => AST::Type(Type::Bool(false), start),
+            Token::Nil => AST::Type(Type::Nil, start),
             Token::Fn => self.parse_fun()?,
             Token::If => self.parse_if()?,
             Token::LBracket => self.parse_array()?,
+            Token::LBrace => self.parse_map()?,
 
-            Token::Ident(ident) => AST::Type(Type::Ident(ident)),
+            Token::Ident(ident) => AST::Type(Type::Ident(ident), start),
 
             Token::Assign | Token::LParen => {
                 let r_side = self.parse_expression(0)?;
                 if matches!(l_side.token, Token::LParen) {
                     self.expect_peek(Token::RParen)?;
-                    AST::Expr(Op::Grouped, vec![r_side])
+                    AST::Expr(Op::Grouped, vec![r_side], start.to(self.previous))
                 } else {
-                    AST::Expr(Op::Assing, vec![r_side])
+                    AST::Expr(Op::Assing, vec![r_side], start.to(self.previous))
                 }
             }
 
-            Token::Len => {
-                self.expect_peek(Token::LParen)?;
-                let right = self.parse_expression(0)?;
-                self.expect_peek(Token::RParen)?;
-                AST::Expr(Op::Len, vec![right])
-            }
-
-            Token::Push => {
-                self.expect_peek(Token::LParen)?;
-                let left = self.parse_expression(0)?;
-                self.expect_peek(Token::Comma)?;
-                let right = self.parse_expression(0)?;
-                self.expect_peek(Token::RParen)?;
-                self.expect_peek(Token::Semicolon)?;
-                return Ok(AST::Expr(Op::Push, vec![left, right]));
-            }
-
-            Token::First => {
-                self.expect_peek(Token::LParen)?;
-                let right = self.parse_expression(0)?;
-                self.expect_peek(Token::RParen)?;
-                AST::Expr(Op::First, vec![right])
-            }
-
-            Token::Last => {
-                self.expect_peek(Token::LParen)?;
-                let right = self.parse_expression(0)?;
-                self.expect_peek(Token::RParen)?;
-                AST::Expr(Op::Last, vec![right])
-            }
+            Token::DotDot | Token::DotDotEqual => {
+                let op = match l_side.token {
+                    Token::DotDot => Op::Range,
+                    _ => Op::RangeInclusive,
+                };
 
-            Token::Rest => {
-                self.expect_peek(Token::LParen)?;
-                let right = self.parse_expression(0)?;
-                self.expect_peek(Token::RParen)?;
-                AST::Expr(Op::Rest, vec![right])
+                // a missing bound takes the place of the `..` itself
+                let end = if self.is_range_end() {
+                    AST::Type(Type::Nil, start)
+                } else {
+                    let (_, r_binding) = self.infix_binding_power(op).unwrap();
+                    self.parse_expression(r_binding)?
+                };
+                AST::Expr(
+                    op,
+                    vec![AST::Type(Type::Nil, start), end],
+                    start.to(self.previous),
+                )
             }
 
             Token::Bang | Token::Minus => {
                 let op = match l_side.token {
                     Token::Bang => Op::Bang,
                     Token::Minus => Op::Minus,
-                    _ => return Ok(AST::Type(Type::Nil)),
+                    _ => return Ok(AST::Type(Type::Nil, start)),
                 };
 
                 let (_, r_binding) = self.prefix_binding_power(op);
                 let r_side = self.parse_expression(r_binding)?;
-                AST::Expr(op, vec![r_side])
+                AST::Expr(op, vec![r_side], start.to(self.previous))
             }
 
             _ => {
-                return Err(anyhow!(
-                    "[line {}] Error: Expected an EXPRESSION",
-                    l_side.line
+                return Err(Error::syntax(
+                    "Expected an EXPRESSION",
+                    Location::Span(start),
                 ))
             }
         };
 
-        loop {
-            let tok = match self.lexer.peek() {
-                Some(Ok(tok)) => tok,
-                _ => break,
-            };
-
+        while let Some(Ok(tok)) = self.lexer.peek() {
+            let op_span = tok.span;
             let op = match tok.token {
                 Token::Plus => Op::Plus,
                 Token::Assign => Op::ReAssign,
+                Token::PlusEqual => Op::PlusAssign,
+                Token::MinusEqual => Op::MinusAssign,
+                Token::StarEqual => Op::StarAssign,
+                Token::SlashEqual => Op::SlashAssign,
                 Token::Minus => Op::Minus,
                 Token::Star => Op::Star,
                 Token::Slash => Op::Slash,
+                Token::Percent => Op::Modulo,
+                Token::StarStar => Op::Power,
+                Token::SlashSlash => Op::FloorDiv,
                 Token::LParen => Op::Fn,
                 Token::AssignEqual => Op::AssignEqual,
                 Token::Bang => Op::Bang,
@@ -196,6 +325,9 @@ impl Parser {
                 Token::And => Op::And,
                 Token::Or => Op::Or,
                 Token::LBracket => Op::Index,
+                Token::Dot => Op::Dot,
+                Token::DotDot => Op::Range,
+                Token::DotDotEqual => Op::RangeInclusive,
                 _ => break,
             };
 
@@ -203,28 +335,56 @@ impl Parser {
                 if l_binding < prev_binding {
                     break;
                 }
+                let start = to_return.span();
                 match op {
                     Op::Fn => {
                         self.expect_peek(Token::LParen)?;
+                        let args = self.parse_args()?;
+                        self.expect_peek(Token::RParen)?;
+                        let span = start.to(self.previous);
                         to_return = AST::Call {
                             calle: Box::new(to_return),
-                            args: self.parse_args()?,
+                            args,
+                            span,
                         };
-                        self.expect_peek(Token::RParen)?;
-                        to_return = AST::Expr(op, vec![to_return]);
+                        to_return = AST::Expr(op, vec![to_return], span);
                     }
 
-                    Op::ReAssign => {
+                    Op::ReAssign
+                    | Op::PlusAssign
+                    | Op::MinusAssign
+                    | Op::StarAssign
+                    | Op::SlashAssign => {
+                        if !matches!(
+                            to_return,
+                            AST::Type(Type::Ident(_), _) | AST::Expr(Op::Index | Op::Dot, _, _)
+                        ) {
+                            return Err(Error::syntax(
+                                format!("invalid assignment target '{to_return}'"),
+                                Location::Span(op_span),
+                            ));
+                        }
+                        // a plain `=` is kept and parsed as part of the value
+                        if op != Op::ReAssign {
+                            self.advance();
+                        }
                         let r_side = self.parse_expression(0)?;
                         self.expect_peek(Token::Semicolon)?;
-                        to_return = AST::Expr(op, vec![to_return, r_side]);
+                        to_return = AST::Expr(op, vec![to_return, r_side], start.to(self.previous));
+                    }
+
+                    Op::Dot => {
+                        self.advance();
+                        let name = self.parse_ident()?;
+                        let name = AST::Type(Type::String(name), self.previous);
+                        to_return = AST::Expr(op, vec![to_return, name], start.to(self.previous));
                     }
 
                     Op::Index => {
                         self.expect_peek(Token::LBracket)?;
                         let r_side = self.parse_expression(0)?;
                         self.expect_peek(Token::RBracket)?;
-                        to_return = AST::Expr(op, vec![to_return, r_side]);
+                        to_return = AST::Expr(op, vec![to_return, r_side], start.to(self.previous));
                     }
 
                     _ => panic!("should not error from postfix"),
@@ -237,9 +397,15 @@ impl Parser {
                 if l_binding < prev_binding {
                     break;
                 }
-                self.lexer.next();
-                let r_side = self.parse_expression(r_binding)?;
-                to_return = AST::Expr(op, vec![to_return, r_side]);
+                self.advance();
+                let r_side = if matches!(op, Op::Range | Op::RangeInclusive) && self.is_range_end()
+                {
+                    AST::Type(Type::Nil, op_span)
+                } else {
+                    self.parse_expression(r_binding)?
+                };
+                let span = to_return.span().to(self.previous);
+                to_return = AST::Expr(op, vec![to_return, r_side], span);
 
                 continue;
             }
@@ -250,17 +416,37 @@ impl Parser {
         return Ok(to_return);
     }
 
+    /// Ranges may leave out their end (`s[2..]`), which shows up as a token
+    /// that closes the surrounding construct instead of an expression.
+    fn is_range_end(&mut self) -> bool {
+        match self.lexer.peek() {
+            Some(Ok(TokenKind { token, .. })) => matches!(
+                token,
+                Token::RBracket
+                    | Token::RParen
+                    | Token::RBrace
+                    | Token::LBrace
+                    | Token::Comma
+                    | Token::Semicolon
+                    | Token::EOF
+            ),
+            _ => true,
+        }
+    }
+
     fn postfix_binding_power(&self, op: Op) -> Option<(u8, ())> {
         match op {
-            Op::Fn | Op::Index | Op::Len => Some((13, ())),
-            Op::ReAssign => Some((12, ())),
+            Op::Fn | Op::Index | Op::Dot => Some((16, ())),
+            Op::ReAssign | Op::PlusAssign | Op::MinusAssign | Op::StarAssign | Op::SlashAssign => {
+                Some((15, ()))
+            }
             _ => None,
         }
     }
 
     fn prefix_binding_power(&self, op: Op) -> ((), u8) {
         match op {
-            Op::Plus | Op::Minus | Op::Bang => ((), 11),
+            Op::Plus | Op::Minus | Op::Bang => ((), 13),
             _ => panic!("bad operation"),
         }
     }
@@ -276,8 +462,11 @@ impl Parser {
             | Op::Greater
             | Op::GreaterEqual => (5, 6),
 
-            Op::Plus | Op::Minus => (7, 8),
-            Op::Star | Op::Slash => (9, 10),
+            Op::Range | Op::RangeInclusive => (7, 8),
+            Op::Plus | Op::Minus => (9, 10),
+            Op::Star | Op::Slash | Op::Modulo | Op::FloorDiv => (11, 12),
+            // right associative and tighter than prefix operators: -2 ** 2 == -(2 ** 2)
+            Op::Power => (14, 13),
             _ => return None,
         };
 
@@ -286,27 +475,36 @@ impl Parser {
 
     fn parse_let(&mut self) -> Result<AST> {
         if self.is_next_token(Token::Let) {
-            self.lexer.next();
+            self.advance();
         }
+        let start = self.previous;
+        let doc = self.doc.take();
 
-        let token = match self.lexer.next() {
+        let token = match self.advance() {
             Some(Ok(token)) => token,
             Some(Err(err)) => return Err(err),
-            _ => return Err(anyhow!("[End of line] Error: Expected an IDENTIFIER")),
+            _ => {
+                return Err(Error::syntax(
+                    "Expected an IDENTIFIER",
+                    Location::EndOfInput,
+                ))
+            }
         };
 
         let ident = match token.token {
             Token::Ident(ident) => ident,
-            _ => return Err(anyhow!("[line: {}] Error: Expected IDENTIFIER", token.line)),
+            _ => {
+                return Err(Error::syntax(
+                    "Expected IDENTIFIER",
+                    Location::Span(token.span),
+                ))
+            }
         };
 
-        let value = match self.lexer.peek() {
-            Some(Ok(TokenKind {
-                token: Token::Assign,
-                ..
-            })) => self.parse_expression(0)?,
-            Some(Err(err)) => panic!("{err}"),
-            _ => AST::Type(Type::Nil),
+        let value = if self.is_next_token(Token::Assign) {
+            self.parse_expression(0)?
+        } else {
+            AST::Type(Type::Nil, token.span)
         };
 
         self.expect_peek(Token::Semicolon)?;
@@ -314,23 +512,29 @@ impl Parser {
         return Ok(AST::Let {
             ident,
             value: Box::new(value),
+            doc,
+            slot: None,
+            span: start.to(self.previous),
         });
     }
 
     fn parse_return(&mut self) -> Result<AST> {
-        self.lexer.next();
+        self.advance();
+        let start = self.previous;
         let value = self.parse_expression(0)?;
         self.expect_peek(Token::Semicolon)?;
 
         return Ok(AST::Return {
             value: Box::new(value),
+            span: start.to(self.previous),
         });
     }
 
     fn parse_if(&mut self) -> Result<AST> {
         if self.is_next_token(Token::If) {
-            self.lexer.next();
+            self.advance();
         }
+        let start = self.previous;
         let condition = self.parse_expression(0)?;
 
         self.expect_peek(Token::LBrace)?;
@@ -341,7 +545,7 @@ impl Parser {
             Some(Ok(TokenKind {
                 token: Token::Else, ..
             })) => {
-                self.lexer.next();
+                self.advance();
                 self.expect_peek(Token::LBrace)?;
                 let no = self.parse_block()?;
                 self.expect_peek(Token::RBrace)?;
@@ -354,15 +558,88 @@ impl Parser {
             condition: Box::new(condition),
             yes,
             no,
+            span: start.to(self.previous),
+        });
+    }
+
+    fn parse_while(&mut self) -> Result<AST> {
+        self.advance();
+        let start = self.previous;
+        let condition = self.parse_expression(0)?;
+
+        self.expect_peek(Token::LBrace)?;
+        let body = self.parse_block()?;
+        self.expect_peek(Token::RBrace)?;
+
+        return Ok(AST::While {
+            condition: Box::new(condition),
+            body,
+            span: start.to(self.previous),
+        });
+    }
+
+    fn parse_for(&mut self) -> Result<AST> {
+        self.advance();
+        let start = self.previous;
+        let mut item = self.parse_ident()?;
+        let mut index = None;
+        if self.is_next_token(Token::Comma) {
+            self.advance();
+            index = Some(item);
+            item = self.parse_ident()?;
+        }
+        self.expect_peek(Token::In)?;
+        let iterable = self.parse_expression(0)?;
+
+        self.expect_peek(Token::LBrace)?;
+        let body = self.parse_block()?;
+        self.expect_peek(Token::RBrace)?;
+
+        return Ok(AST::For {
+            index,
+            item,
+            iterable: Box::new(iterable),
+            body,
+            span: start.to(self.previous),
         });
     }
 
+    fn parse_ident(&mut self) -> Result<Rc<str>> {
+        let token = match self.advance() {
+            Some(Ok(token)) => token,
+            Some(Err(err)) => return Err(err),
+            None => return Err(Error::syntax("Expected IDENTIFIER", Location::EndOfInput)),
+        };
+
+        match token.token {
+            Token::Ident(ident) => Ok(ident),
+            _ => Err(Error::syntax(
+                "Expected IDENTIFIER",
+                Location::Span(token.span),
+            )),
+        }
+    }
+
+    fn parse_loop_control(&mut self, ast: fn(Span) -> AST) -> Result<AST> {
+        self.advance();
+        let start = self.previous;
+        self.expect_peek(Token::Semicolon)?;
+
+        return Ok(ast(start.to(self.previous)));
+    }
+
+    /// Parses statements up to the closing brace, which is left for the
+    /// caller. Broken statements are reported and skipped like at the top
+    /// level, so an error doesn't hide the rest of the block.
     fn parse_block(&mut self) -> Result<Rc<[AST]>> {
         let mut to_return = Vec::new();
-        for result in self.parse_statement() {
-            match result {
+        while !self.is_next_token(Token::RBrace) && !self.at_end() {
+            match self.parse_statement() {
                 Ok(ast) => to_return.push(ast),
-                Err(err) => return Err(err),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
             };
         }
 
@@ -371,8 +648,10 @@ impl Parser {
 
     fn parse_fun(&mut self) -> Result<AST> {
         if self.is_next_token(Token::Fn) {
-            self.lexer.next();
+            self.advance();
         }
+        let start = self.previous;
+        let doc = self.doc.take();
 
         let name = if let Some(Ok(TokenKind {
             token: Token::Ident(val),
@@ -380,7 +659,7 @@ impl Parser {
         })) = self.lexer.peek()
         {
             let name = val.clone();
-            self.lexer.next();
+            self.advance();
             Some(name)
         } else {
             None
@@ -394,36 +673,25 @@ impl Parser {
         let body = self.parse_block()?;
         self.expect_peek(Token::RBrace)?;
 
-        return Ok(AST::Fn { name, params, body });
+        return Ok(AST::Fn {
+            name,
+            params,
+            body,
+            doc,
+            slot: None,
+            span: start.to(self.previous),
+        });
     }
 
     fn parse_params(&mut self) -> Result<Rc<[Rc<str>]>> {
         let mut params: Vec<Rc<str>> = Vec::new();
         if !self.is_next_token(Token::RParen) {
-            let token = match self.lexer.next() {
-                Some(Ok(token)) => token,
-                Some(Err(err)) => return Err(anyhow!("{err}")),
-                None => return Err(anyhow!("[End of line] Error: Expected IDENTIFIER")),
-            };
-
-            match token.token {
-                Token::Ident(ident) => params.push(ident),
-                _ => return Err(anyhow!("[line: {}] Error: Expected IDENTIFIER", token.line)),
-            }
+            params.push(self.parse_ident()?);
         }
 
         while self.is_next_token(Token::Comma) {
-            self.lexer.next(); // comsume comma
-            let token = match self.lexer.next() {
-                Some(Ok(token)) => token,
-                Some(Err(err)) => return Err(anyhow!("{err}")),
-                None => return Err(anyhow!("[End of line] Error: Expected IDENTIFIER")),
-            };
-
-            match token.token {
-                Token::Ident(ident) => params.push(ident),
-                _ => return Err(anyhow!("[line: {}] Error: Expected IDENTIFIER", token.line)),
-            }
+            self.advance(); // comsume comma
+            params.push(self.parse_ident()?);
         }
 
         return Ok(params.into());
@@ -437,39 +705,27 @@ impl Parser {
         }
 
         while self.is_next_token(Token::Comma) {
-            self.lexer.next(); // comsume comma
+            self.advance(); // comsume comma
             params.push(self.parse_expression(0)?);
         }
 
         return Ok(params.into());
     }
 
-    fn parse_print(&mut self) -> Result<AST> {
-        self.lexer.next();
-
-        match self.lexer.peek() {
-            Some(Ok(_)) => {
-                let to_return = self.parse_expression(0)?;
-                self.expect_peek(Token::Semicolon)?;
-                Ok(AST::Print(Box::new(to_return)))
-            }
-            _ => Err(anyhow!("expected an expression")),
-        }
-    }
-
     fn parse_expression_statements(&mut self) -> Result<AST> {
         let expr = self.parse_expression(0)?;
         if self.is_next_token(Token::Semicolon) {
-            self.lexer.next();
+            self.advance();
         }
         return Ok(expr);
     }
 
     fn parse_array(&mut self) -> Result<AST> {
+        let start = self.previous;
         let mut vector = Vec::new();
         loop {
             if self.is_next_token(Token::RBracket) {
-                self.lexer.next();
+                self.advance();
                 break;
             }
             vector.push(self.parse_expression(0)?);
@@ -481,12 +737,85 @@ impl Parser {
                 Some(Ok(TokenKind {
                     token: Token::Comma,
                     ..
-                })) => self.lexer.next(),
-                _ => return Err(anyhow!("expected ',' or ']' in array literal")),
+                })) => self.advance(),
+                _ => return Err(self.unexpected("expected ',' or ']' in array literal")),
             };
         }
 
-        return Ok(AST::Type(Type::Arr(Box::new(vector))));
+        return Ok(AST::Type(
+            Type::Arr(Box::new(vector)),
+            start.to(self.previous),
+        ));
+    }
+
+    /// Literal parts share the span of the whole template, interpolated
+    /// expressions get their own as they are lexed at their place in it.
+    fn parse_template(&mut self, parts: Rc<[StringPart]>, span: Span) -> Result<AST> {
+        let mut asts = Vec::new();
+        for part in parts.iter() {
+            match part {
+                StringPart::Literal(str) => asts.push(AST::Type(Type::String(str.clone()), span)),
+                StringPart::Expr {
+                    source,
+                    span: source_span,
+                } => {
+                    let mut parser = Parser {
+                        lexer: Lexer::with_span(source.to_string(), *source_span).peekable(),
+                        previous: *source_span,
+                        doc: None,
+                        errors: Vec::new(),
+                    };
+                    if parser.is_next_token(Token::EOF) {
+                        return Err(Error::syntax(
+                            "Empty interpolation",
+                            Location::Span(*source_span),
+                        ));
+                    }
+                    let expr = parser.parse_expression(0);
+                    self.errors.append(&mut parser.errors);
+                    asts.push(expr?);
+                    if !parser.is_next_token(Token::EOF) {
+                        return Err(Error::syntax(
+                            "expected '}' to close interpolation",
+                            parser.peek_location(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        return Ok(AST::Type(Type::Template(Box::new(asts)), span));
+    }
+
+    fn parse_map(&mut self) -> Result<AST> {
+        let start = self.previous;
+        let mut pairs = Vec::new();
+        loop {
+            if self.is_next_token(Token::RBrace) {
+                self.advance();
+                break;
+            }
+            let key = self.parse_expression(0)?;
+            self.expect_peek(Token::Colon)?;
+            let value = self.parse_expression(0)?;
+            pairs.push((key, value));
+            match self.lexer.peek() {
+                Some(Ok(TokenKind {
+                    token: Token::RBrace,
+                    ..
+                })) => continue,
+                Some(Ok(TokenKind {
+                    token: Token::Comma,
+                    ..
+                })) => self.advance(),
+                _ => return Err(self.unexpected("expected ',' or '}' in map literal")),
+            };
+        }
+
+        return Ok(AST::Type(
+            Type::Map(Box::new(pairs)),
+            start.to(self.previous),
+        ));
     }
 }
 
@@ -496,6 +825,9 @@ pub enum Op {
     Minus,
     Star,
     Slash,
+    Modulo,
+    Power,
+    FloorDiv,
     Bang,
     Grouped,
     Assing,
@@ -509,12 +841,14 @@ pub enum Op {
     Or,
     And,
     Index,
+    Dot,
+    Range,
+    RangeInclusive,
     ReAssign,
-    Len,
-    First,
-    Last,
-    Push,
-    Rest,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
 }
 
 impl fmt::Display for Op {
@@ -525,6 +859,10 @@ impl fmt::Display for Op {
             match self {
                 Op::Minus => "-",
                 Op::ReAssign => "=",
+                Op::PlusAssign => "+=",
+                Op::MinusAssign => "-=",
+                Op::StarAssign => "*=",
+                Op::SlashAssign => "/=",
                 Op::Plus => "+",
                 Op::Star => "*",
                 Op::Assing => "=",
@@ -535,17 +873,18 @@ impl fmt::Display for Op {
                 Op::Less => "<",
                 Op::Greater => ">",
                 Op::Slash => "/",
+                Op::Modulo => "%",
+                Op::Power => "**",
+                Op::FloorDiv => "//",
                 Op::Bang => "!",
                 Op::And => "and",
                 Op::Or => "or",
                 Op::Fn => "call",
                 Op::Grouped => "group",
-                Op::Len => "len",
                 Op::Index => "index",
-                Op::First => "First",
-                Op::Last => "Last",
-                Op::Push => "Push",
-                Op::Rest => "Rest",
+                Op::Dot => ".",
+                Op::Range => "..",
+                Op::RangeInclusive => "..=",
             }
         )
     }
@@ -554,10 +893,13 @@ impl fmt::Display for Op {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     String(Rc<str>),
-    Number(f64),
+    Int(i64),
+    Float(f64),
     Ident(Rc<str>),
     Bool(bool),
     Arr(Box<Vec<AST>>),
+    Map(Box<Vec<(AST, AST)>>),
+    Template(Box<Vec<AST>>),
     Nil,
 }
 
@@ -567,14 +909,9 @@ impl fmt::Display for Type {
             // NOTE: this feels more correct:
             // Type::String(s) => write!(f, "\"{s}\""),
             Type::String(s) => write!(f, "{s}"),
-            Type::Number(n) => {
-                if *n == n.trunc() {
-                    // tests require that integers are printed as N.0
-                    write!(f, "{n}.0")
-                } else {
-                    write!(f, "{n}")
-                }
-            }
+            Type::Int(n) => write!(f, "{n}"),
+            // floats always show a fraction so they can't be mistaken for ints
+            Type::Float(n) => write!(f, "{n:?}"),
             Type::Nil => write!(f, "nil"),
             Type::Bool(b) => write!(f, "{b:?}"),
             Type::Ident(i) => write!(f, "{i}"),
@@ -588,58 +925,264 @@ impl fmt::Display for Type {
                 }
                 write!(f, "]")
             }
+            Type::Template(parts) => {
+                write!(f, "\"")?;
+                for part in parts.iter() {
+                    match part {
+                        AST::Type(Type::String(str), _) => write!(f, "{str}")?,
+                        expr => write!(f, "${{{expr}}}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Type::Map(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A node of the syntax tree, each one carrying the span of the source it was
+/// parsed from.
+#[derive(Debug, Clone)]
 pub enum AST {
-    Type(Type),
-
-    Expr(Op, Vec<AST>),
+    Type(Type, Span),
 
-    Print(Box<AST>),
+    Expr(Op, Vec<AST>, Span),
 
     Let {
         ident: Rc<str>,
         value: Box<AST>, // Expr
+        /// From the `///` comments right before the `let`.
+        doc: Option<Rc<str>>,
+        /// Given by the [`Resolver`](crate::resolver::Resolver) when it's
+        /// declared in a function or loop scope instead of the globals.
+        slot: Option<usize>,
+        span: Span,
     },
 
     Fn {
         name: Option<Rc<str>>,
         params: Rc<[Rc<str>]>,
         body: Rc<[AST]>,
+        /// From the `///` comments right before the `fn`.
+        doc: Option<Rc<str>>,
+        /// Like the one of `let`, for named functions.
+        slot: Option<usize>,
+        span: Span,
     },
 
     Call {
         calle: Box<AST>,
         args: Rc<[AST]>,
+        span: Span,
     },
 
     Return {
         value: Box<AST>, // Expr
+        span: Span,
     },
 
     If {
         condition: Box<AST>,
         yes: Rc<[AST]>,
         no: Option<Rc<[AST]>>,
+        span: Span,
+    },
+
+    While {
+        condition: Box<AST>,
+        body: Rc<[AST]>,
+        span: Span,
+    },
+
+    /// `for item in iterable` or `for index, item in iterable`. Maps bind
+    /// their keys to `item`, or key and value to `index` and `item`.
+    For {
+        index: Option<Rc<str>>,
+        item: Rc<str>,
+        iterable: Box<AST>,
+        body: Rc<[AST]>,
+        span: Span,
+    },
+
+    Break(Span),
+
+    Continue(Span),
+
+    /// An identifier the resolver found in the `slot` of a function or loop
+    /// scope, `depth` scopes out from where it's used. Globals stay
+    /// [`Type::Ident`]s, looked up by name.
+    Local {
+        name: Rc<str>,
+        depth: usize,
+        slot: usize,
+        span: Span,
     },
 }
 
+impl AST {
+    pub fn span(&self) -> Span {
+        match self {
+            AST::Type(_, span)
+            | AST::Expr(_, _, span)
+            | AST::Let { span, .. }
+            | AST::Fn { span, .. }
+            | AST::Call { span, .. }
+            | AST::Return { span, .. }
+            | AST::If { span, .. }
+            | AST::While { span, .. }
+            | AST::For { span, .. }
+            | AST::Break(span)
+            | AST::Continue(span)
+            | AST::Local { span, .. } => *span,
+        }
+    }
+}
+
+/// Trees compare by structure alone, spans are left out so that a parsed
+/// program can be checked against one built by hand.
+impl PartialEq for AST {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (AST::Type(left, _), AST::Type(right, _)) => left == right,
+            (AST::Expr(left_op, left, _), AST::Expr(right_op, right, _)) => {
+                left_op == right_op && left == right
+            }
+            (
+                AST::Let {
+                    ident,
+                    value,
+                    doc,
+                    slot,
+                    ..
+                },
+                AST::Let {
+                    ident: other_ident,
+                    value: other_value,
+                    doc: other_doc,
+                    slot: other_slot,
+                    ..
+                },
+            ) => {
+                ident == other_ident
+                    && value == other_value
+                    && doc == other_doc
+                    && slot == other_slot
+            }
+            (
+                AST::Fn {
+                    name,
+                    params,
+                    body,
+                    doc,
+                    slot,
+                    ..
+                },
+                AST::Fn {
+                    name: other_name,
+                    params: other_params,
+                    body: other_body,
+                    doc: other_doc,
+                    slot: other_slot,
+                    ..
+                },
+            ) => {
+                name == other_name
+                    && params == other_params
+                    && body == other_body
+                    && doc == other_doc
+                    && slot == other_slot
+            }
+            (
+                AST::Call { calle, args, .. },
+                AST::Call {
+                    calle: other_calle,
+                    args: other_args,
+                    ..
+                },
+            ) => calle == other_calle && args == other_args,
+            (AST::Return { value, .. }, AST::Return { value: other, .. }) => value == other,
+            (
+                AST::If {
+                    condition, yes, no, ..
+                },
+                AST::If {
+                    condition: other_condition,
+                    yes: other_yes,
+                    no: other_no,
+                    ..
+                },
+            ) => condition == other_condition && yes == other_yes && no == other_no,
+            (
+                AST::While {
+                    condition, body, ..
+                },
+                AST::While {
+                    condition: other_condition,
+                    body: other_body,
+                    ..
+                },
+            ) => condition == other_condition && body == other_body,
+            (
+                AST::For {
+                    index,
+                    item,
+                    iterable,
+                    body,
+                    ..
+                },
+                AST::For {
+                    index: other_index,
+                    item: other_item,
+                    iterable: other_iterable,
+                    body: other_body,
+                    ..
+                },
+            ) => {
+                index == other_index
+                    && item == other_item
+                    && iterable == other_iterable
+                    && body == other_body
+            }
+            (AST::Break(_), AST::Break(_)) | (AST::Continue(_), AST::Continue(_)) => true,
+            (
+                AST::Local {
+                    name, depth, slot, ..
+                },
+                AST::Local {
+                    name: other_name,
+                    depth: other_depth,
+                    slot: other_slot,
+                    ..
+                },
+            ) => name == other_name && depth == other_depth && slot == other_slot,
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for AST {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            AST::Type(i) => write!(f, "{}", i),
-            AST::Print(i) => write!(f, "{}", i),
-            AST::Expr(head, rest) => {
+            AST::Type(i, _) => write!(f, "{}", i),
+            AST::Expr(head, rest, _) => {
                 write!(f, "({}", head)?;
                 for s in rest {
                     write!(f, " {s}")?
                 }
                 write!(f, ")")
             }
-            AST::Fn { name, params, body } => {
+            AST::Fn {
+                name, params, body, ..
+            } => {
                 if let Some(val) = name {
                     write!(f, "{val}")?;
                 } else {
@@ -654,14 +1197,16 @@ impl fmt::Display for AST {
                 }
                 write!(f, "}}")
             }
-            AST::Call { calle, args } => {
+            AST::Call { calle, args, .. } => {
                 write!(f, "({calle}")?;
                 for a in args.iter() {
                     write!(f, " {a}")?
                 }
                 write!(f, ")")
             }
-            AST::If { condition, yes, no } => {
+            AST::If {
+                condition, yes, no, ..
+            } => {
                 write!(f, "if {condition} {{")?;
                 for stmt in yes.iter() {
                     write!(f, " {stmt}")?;
@@ -678,13 +1223,42 @@ impl fmt::Display for AST {
                 }
                 write!(f, "")
             }
-            AST::Return { value } => {
+            AST::Return { value, .. } => {
                 write!(f, "return {value}")
             }
-            AST::Let { ident, value } => {
+            AST::While {
+                condition, body, ..
+            } => {
+                write!(f, "while {condition} {{")?;
+                for stmt in body.iter() {
+                    write!(f, " {stmt}")?;
+                }
+                write!(f, " }}")
+            }
+            AST::For {
+                index,
+                item,
+                iterable,
+                body,
+                ..
+            } => {
+                write!(f, "for ")?;
+                if let Some(index) = index {
+                    write!(f, "{index}, ")?;
+                }
+                write!(f, "{item} in {iterable} {{")?;
+                for stmt in body.iter() {
+                    write!(f, " {stmt}")?;
+                }
+                write!(f, " }}")
+            }
+            AST::Break(_) => write!(f, "break"),
+            AST::Continue(_) => write!(f, "continue"),
+            AST::Let { ident, value, .. } => {
                 write!(f, "{ident}")?;
                 write!(f, "{value}")
             }
+            AST::Local { name, .. } => write!(f, "{name}"),
         }
     }
 }
@@ -695,34 +1269,53 @@ mod tests {
     use std::rc::Rc;
 
     use super::{Op, Parser, Type, AST};
-    use anyhow::Result;
+    use crate::{
+        error::{Error, Location, Result},
+        lexer::Span,
+    };
 
     #[test]
     fn expressions_statement() -> Result<()> {
         let test_cases = vec![
             // Input, Expected Output
-            ("(-2 - 1) * 3", "(* (group (- (- 2.0) 1.0)) 3.0)"),
-            ("3 - (-2)", "(- 3.0 (group (- 2.0)))"),
+            ("(-2 - 1) * 3", "(* (group (- (- 2) 1)) 3)"),
+            ("3 - (-2)", "(- 3 (group (- 2)))"),
             (
                 "23 + (5 - 3 * 5) / 10;",
-                "(+ 23.0 (/ (group (- 5.0 (* 3.0 5.0))) 10.0))",
+                "(+ 23 (/ (group (- 5 (* 3 5))) 10))",
             ),
             (
                 "(10 - 0) * 10 > 4 / 3;",
-                "(> (* (group (- 10.0 0.0)) 10.0) (/ 4.0 3.0))",
+                "(> (* (group (- 10 0)) 10) (/ 4 3))",
             ),
+            ("1.5 * 2.", "(* 1.5 2.0)"),
             ("!true", "(! true)"),
             ("!!false != true", "(!= (! (! false)) true)"),
             (
                 "dos + 3 - mutilple(3) / 100;",
-                "(- (+ dos 3.0) (/ (call (mutilple 3.0)) 100.0))",
+                "(- (+ dos 3) (/ (call (mutilple 3)) 100))",
             ),
+            (r#""a${b + 1}c";"#, r#""a${(+ b 1)}c""#),
+            (
+                "xs.rest().first()",
+                "(call ((. (call ((. xs rest))) first)))",
+            ),
+            ("m.a.b = 1;", "(= (. (. m a) b) (= 1))"),
+            ("x = 1;", "(= x (= 1))"),
+            ("x += 1;", "(+= x 1)"),
+            ("a[i][j] *= 2;", "(*= (index (index a i) j) 2)"),
+            ("-2 ** 2", "(- (** 2 2))"),
+            ("2 ** 3 ** 2", "(** 2 (** 3 2))"),
+            ("7 % 3 * 2 // 4", "(// (* (% 7 3) 2) 4)"),
+            ("arr[1..n - 1]", "(index arr (.. 1 (- n 1)))"),
+            ("arr[-2..]", "(index arr (.. (- 2) nil))"),
+            ("arr[..=2]", "(index arr (..= nil 2))"),
         ];
 
         for (input, expected) in test_cases {
             let asts = Parser::new(input.to_owned()).parse();
             for result in asts {
-                assert_eq!(false, matches!(result, Err(_)));
+                assert!(result.is_ok());
                 assert_eq!(expected, format!("{}", result.unwrap()).as_str());
             }
         }
@@ -738,18 +1331,39 @@ mod tests {
         "#
         .to_string();
 
-        let expected = vec![
+        let expected = [
             AST::Let {
                 ident: "num".into(),
-                value: Box::new(AST::Expr(Op::Assing, vec![AST::Type(Type::Number(1.0))])),
+                value: Box::new(AST::Expr(
+                    Op::Assing,
+                    vec![AST::Type(Type::Int(1), Span::default())],
+                    Span::default(),
+                )),
+                doc: None,
+                slot: None,
+                span: Span::default(),
             },
             AST::Let {
                 ident: "num2".into(),
-                value: Box::new(AST::Expr(Op::Assing, vec![AST::Type(Type::Number(2.0))])),
+                value: Box::new(AST::Expr(
+                    Op::Assing,
+                    vec![AST::Type(Type::Int(2), Span::default())],
+                    Span::default(),
+                )),
+                doc: None,
+                slot: None,
+                span: Span::default(),
             },
             AST::Let {
                 ident: "num3".into(),
-                value: Box::new(AST::Expr(Op::Assing, vec![AST::Type(Type::Number(3.0))])),
+                value: Box::new(AST::Expr(
+                    Op::Assing,
+                    vec![AST::Type(Type::Int(3), Span::default())],
+                    Span::default(),
+                )),
+                doc: None,
+                slot: None,
+                span: Span::default(),
             },
         ];
 
@@ -767,10 +1381,22 @@ mod tests {
         Ok(())
     }
 
+    fn call<const N: usize>(name: &str, args: [AST; N]) -> AST {
+        return AST::Expr(
+            Op::Fn,
+            vec![AST::Call {
+                calle: Box::new(AST::Type(Type::Ident(name.into()), Span::default())),
+                args: Rc::new(args),
+                span: Span::default(),
+            }],
+            Span::default(),
+        );
+    }
+
     #[test]
-    fn print_stmt() -> Result<()> {
-        let input = "print 42;";
-        let expected = AST::Print(Box::new(AST::Type(Type::Number(42.0))));
+    fn print_call() -> Result<()> {
+        let input = "print(42);";
+        let expected = call("print", [AST::Type(Type::Int(42), Span::default())]);
 
         let mut parser = Parser::new(input.to_string());
         let statements = parser.parse();
@@ -778,7 +1404,7 @@ mod tests {
 
         match &statements[0] {
             Ok(ast) => assert_eq!(ast, &expected),
-            Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+            Err(err) => return Err(err.clone()),
         }
 
         Ok(())
@@ -789,7 +1415,7 @@ mod tests {
     //     let input = "x = 10;";
     //     let expected = AST::Reassign {
     //         ident: "x".into(),
-    //         value: Box::new(AST::Expr(Op::Assing, vec![AST::Type(Type::Number(10.0))])),
+    //         value: Box::new(AST::Expr(Op::Assing, vec![AST::Type(Type::Int(10))])),
     //     };
     //
     //     let mut parser = Parser::new(input.to_string());
@@ -798,7 +1424,7 @@ mod tests {
     //
     //     match &statements[0] {
     //         Ok(ast) => assert_eq!(ast, &expected),
-    //         Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+    //         Err(err) => return Err(err.clone()),
     //     }
     //
     //     Ok(())
@@ -807,7 +1433,10 @@ mod tests {
     #[test]
     fn len_expr() -> Result<()> {
         let input = "len(\"hello\");";
-        let expected = AST::Expr(Op::Len, vec![AST::Type(Type::String("hello".into()))]);
+        let expected = call(
+            "len",
+            [AST::Type(Type::String("hello".into()), Span::default())],
+        );
 
         let mut parser = Parser::new(input.to_string());
         let statements = parser.parse();
@@ -815,7 +1444,149 @@ mod tests {
 
         match &statements[0] {
             Ok(ast) => assert_eq!(ast, &expected),
-            Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+            Err(err) => return Err(err.clone()),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn while_stmt() -> Result<()> {
+        let input = "while x < 10 { if x == 5 { break; } continue; }";
+        let expected = AST::While {
+            condition: Box::new(AST::Expr(
+                Op::Less,
+                vec![
+                    AST::Type(Type::Ident("x".into()), Span::default()),
+                    AST::Type(Type::Int(10), Span::default()),
+                ],
+                Span::default(),
+            )),
+            body: Rc::new([
+                AST::If {
+                    condition: Box::new(AST::Expr(
+                        Op::AssignEqual,
+                        vec![
+                            AST::Type(Type::Ident("x".into()), Span::default()),
+                            AST::Type(Type::Int(5), Span::default()),
+                        ],
+                        Span::default(),
+                    )),
+                    yes: Rc::new([AST::Break(Span::default())]),
+                    no: None,
+                    span: Span::default(),
+                },
+                AST::Continue(Span::default()),
+            ]),
+            span: Span::default(),
+        };
+
+        let mut parser = Parser::new(input.to_string());
+        let statements = parser.parse();
+        assert_eq!(statements.len(), 1);
+
+        match &statements[0] {
+            Ok(ast) => assert_eq!(ast, &expected),
+            Err(err) => return Err(err.clone()),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn for_stmt() -> Result<()> {
+        let input = "for i, x in 0..=len(xs) { print(x); }";
+        let expected = AST::For {
+            index: Some("i".into()),
+            item: "x".into(),
+            iterable: Box::new(AST::Expr(
+                Op::RangeInclusive,
+                vec![
+                    AST::Type(Type::Int(0), Span::default()),
+                    call(
+                        "len",
+                        [AST::Type(Type::Ident("xs".into()), Span::default())],
+                    ),
+                ],
+                Span::default(),
+            )),
+            body: Rc::new([call(
+                "print",
+                [AST::Type(Type::Ident("x".into()), Span::default())],
+            )]),
+            span: Span::default(),
+        };
+
+        let mut parser = Parser::new(input.to_string());
+        let statements = parser.parse();
+        assert_eq!(statements.len(), 1);
+
+        match &statements[0] {
+            Ok(ast) => assert_eq!(ast, &expected),
+            Err(err) => return Err(err.clone()),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn map_literal() -> Result<()> {
+        let input = r#"{"name": "x", 1: true};"#;
+        let expected = AST::Type(
+            Type::Map(Box::new(vec![
+                (
+                    AST::Type(Type::String("name".into()), Span::default()),
+                    AST::Type(Type::String("x".into()), Span::default()),
+                ),
+                (
+                    AST::Type(Type::Int(1), Span::default()),
+                    AST::Type(Type::Bool(true), Span::default()),
+                ),
+            ])),
+            Span::default(),
+        );
+
+        let mut parser = Parser::new(input.to_string());
+        let statements = parser.parse();
+        assert_eq!(statements.len(), 1);
+
+        match &statements[0] {
+            Ok(ast) => assert_eq!(ast, &expected),
+            Err(err) => return Err(err.clone()),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn template_errors() -> Result<()> {
+        let cases = [
+            (
+                "\n\"${}\";",
+                Error::syntax("Empty interpolation", Location::Span(Span::new(4, 0, 2, 4))),
+            ),
+            (
+                "\"${a b}\";",
+                Error::syntax(
+                    "expected '}' to close interpolation",
+                    Location::Span(Span::new(5, 1, 1, 6)),
+                ),
+            ),
+            (
+                "\"a\n${ ) }\";",
+                Error::syntax(
+                    "Expected an EXPRESSION",
+                    Location::Span(Span::new(6, 1, 2, 4)),
+                ),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let statements = Parser::new(input.to_string()).parse();
+            match &statements[0] {
+                Ok(ast) => panic!("expected an error, got: {ast}"),
+                Err(err) => assert_eq!(&expected, err),
+            }
         }
 
         Ok(())
@@ -831,11 +1602,16 @@ mod tests {
                 value: Box::new(AST::Expr(
                     Op::Plus,
                     vec![
-                        AST::Type(Type::Ident("a".into())),
-                        AST::Type(Type::Ident("b".into())),
+                        AST::Type(Type::Ident("a".into()), Span::default()),
+                        AST::Type(Type::Ident("b".into()), Span::default()),
                     ],
+                    Span::default(),
                 )),
+                span: Span::default(),
             }]),
+            doc: None,
+            slot: None,
+            span: Span::default(),
         };
 
         let mut parser = Parser::new(input.to_string());
@@ -844,7 +1620,7 @@ mod tests {
 
         match &statements[0] {
             Ok(ast) => assert_eq!(ast, &expected),
-            Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+            Err(err) => return Err(err.clone()),
         }
 
         Ok(())
@@ -856,9 +1632,14 @@ mod tests {
         let expected = AST::Expr(
             Op::Fn,
             vec![AST::Call {
-                calle: Box::new(AST::Type(Type::Ident("add".into()))),
-                args: Rc::new([AST::Type(Type::Number(1.0)), AST::Type(Type::Number(2.0))]),
+                calle: Box::new(AST::Type(Type::Ident("add".into()), Span::default())),
+                args: Rc::new([
+                    AST::Type(Type::Int(1), Span::default()),
+                    AST::Type(Type::Int(2), Span::default()),
+                ]),
+                span: Span::default(),
             }],
+            Span::default(),
         );
 
         let mut parser = Parser::new(input.to_string());
@@ -867,7 +1648,7 @@ mod tests {
 
         match &statements[0] {
             Ok(ast) => assert_eq!(ast, &expected),
-            Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+            Err(err) => return Err(err.clone()),
         }
 
         Ok(())
@@ -877,7 +1658,8 @@ mod tests {
     fn return_stmt() -> Result<()> {
         let input = "return 42;";
         let expected = AST::Return {
-            value: Box::new(AST::Type(Type::Number(42.0))),
+            value: Box::new(AST::Type(Type::Int(42), Span::default())),
+            span: Span::default(),
         };
 
         let mut parser = Parser::new(input.to_string());
@@ -886,7 +1668,7 @@ mod tests {
 
         match &statements[0] {
             Ok(ast) => assert_eq!(ast, &expected),
-            Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+            Err(err) => return Err(err.clone()),
         }
 
         Ok(())
@@ -894,19 +1676,25 @@ mod tests {
 
     #[test]
     fn if_stmt() -> Result<()> {
-        let input = "if x > 0 { print x; } else { print 0; }";
+        let input = "if x > 0 { print(x); } else { print(0); }";
         let expected = AST::If {
             condition: Box::new(AST::Expr(
                 Op::Greater,
                 vec![
-                    AST::Type(Type::Ident("x".into())),
-                    AST::Type(Type::Number(0.0)),
+                    AST::Type(Type::Ident("x".into()), Span::default()),
+                    AST::Type(Type::Int(0), Span::default()),
                 ],
+                Span::default(),
             )),
-            yes: Rc::new([AST::Print(Box::new(AST::Type(Type::Ident("x".into()))))]),
-            no: Some(Rc::new([AST::Print(Box::new(AST::Type(Type::Number(
-                0.0,
-            ))))])),
+            yes: Rc::new([call(
+                "print",
+                [AST::Type(Type::Ident("x".into()), Span::default())],
+            )]),
+            no: Some(Rc::new([call(
+                "print",
+                [AST::Type(Type::Int(0), Span::default())],
+            )])),
+            span: Span::default(),
         };
 
         let mut parser = Parser::new(input.to_string());
@@ -915,9 +1703,109 @@ mod tests {
 
         match &statements[0] {
             Ok(ast) => assert_eq!(ast, &expected),
-            Err(err) => return Err(anyhow::anyhow!("Parsing failed: {}", err)),
+            Err(err) => return Err(err.clone()),
         }
 
         Ok(())
     }
+
+    #[test]
+    fn doc_comments() -> Result<()> {
+        let input = "\
+/// Adds two numbers.
+/// Ints or floats.
+fn add(a, b) {
+    /// Not a function.
+    let sum = a + b;
+    return sum;
+}
+/// Doubles it.
+let double = fn(x) { x * 2 };
+/// Goes nowhere.
+double(2);
+let plain = 1;";
+
+        let statements = Parser::new(input.to_string())
+            .parse()
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        let AST::Fn { doc, body, .. } = &statements[0] else {
+            panic!("expected a function, got {:?}", statements[0]);
+        };
+        assert_eq!(Some("Adds two numbers.\nInts or floats.".into()), *doc);
+        let AST::Let { doc, .. } = &body[0] else {
+            panic!("expected a let, got {:?}", body[0]);
+        };
+        assert_eq!(Some("Not a function.".into()), *doc);
+
+        let docs: Vec<Option<Rc<str>>> = statements[1..]
+            .iter()
+            .filter_map(|ast| match ast {
+                AST::Let { doc, value, .. } => {
+                    if let AST::Fn { doc: fn_doc, .. } = &**value {
+                        assert_eq!(None, *fn_doc);
+                    }
+                    Some(doc.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![Some("Doubles it.".into()), None], docs);
+
+        Ok(())
+    }
+
+    #[test]
+    fn node_spans() -> Result<()> {
+        let input = "let total = price * 2;\nprint(total);";
+        let statements = Parser::new(input.to_string())
+            .parse()
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(Span::new(0, 22, 1, 1), statements[0].span());
+        assert_eq!(Span::new(23, 12, 2, 1), statements[1].span());
+
+        let AST::Let { value, .. } = &statements[0] else {
+            panic!("expected a let statement, got: {}", statements[0]);
+        };
+        let AST::Expr(Op::Assing, operands, span) = value.as_ref() else {
+            panic!("expected an assignment, got: {value}");
+        };
+        assert_eq!(Span::new(10, 11, 1, 11), *span);
+        assert_eq!(Span::new(12, 9, 1, 13), operands[0].span());
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_recovery() -> Result<()> {
+        let input = "let a = ;\nlet b = 2;\n}\nfn f() { let c = ; return 1 }\nelse { print(0); }\nprint(b);";
+        let syntax = |message: &str, offset, len, line, column| {
+            Err(Error::syntax(
+                message,
+                Location::Span(Span::new(offset, len, line, column)),
+            ))
+        };
+        let expected: Vec<Result<String>> = vec![
+            syntax("Expected an EXPRESSION", 8, 1, 1, 9),
+            Ok("b(= 2)".to_string()),
+            syntax("unexpected RIGHT_BRACE }", 21, 1, 3, 1),
+            syntax("Expected an EXPRESSION", 40, 1, 4, 18),
+            syntax("expected SEMICOLON ;", 51, 1, 4, 29),
+            Ok("f}".to_string()),
+            syntax("unexpected ELSE else", 53, 4, 5, 1),
+            Ok("(call (print b))".to_string()),
+        ];
+
+        let statements: Vec<Result<String>> = Parser::new(input.to_string())
+            .parse()
+            .into_iter()
+            .map(|result| result.map(|ast| ast.to_string()))
+            .collect();
+        assert_eq!(expected, statements);
+
+        Ok(())
+    }
 }
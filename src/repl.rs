@@ -1,7 +1,7 @@
-use anyhow::Result;
-use std::io::{self, Write};
+use std::io::{self, Result, Write};
 
 use crate::{
+    diagnostic::Diagnostic,
     eval::{Env, Evaluator},
     parser::Parser,
 };
@@ -12,7 +12,7 @@ pub fn start() -> Result<()> {
     println!("Feel free to type in commands");
 
     let env = Env::new();
-    let mut evalator = Evaluator::new(env, io::stdout(), io::stderr());
+    let mut evalator = Evaluator::new(env, io::stdout());
 
     loop {
         print!(">>");
@@ -21,9 +21,22 @@ pub fn start() -> Result<()> {
         stdin.read_line(&mut line)?;
 
         line = line.trim().to_string();
-        let mut parser = Parser::new(line);
+        let mut parser = Parser::new(line.clone());
+        let program = parser.parse();
+        let errors: Vec<_> = program
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .collect();
+        if !errors.is_empty() {
+            for err in errors {
+                Diagnostic::from(err).emit("<repl>", &line);
+            }
+            continue;
+        }
 
-        let result = evalator.eval(parser.parse());
-        println!("{result}");
+        match evalator.eval(program) {
+            Ok(result) => println!("{result}"),
+            Err(err) => Diagnostic::from(&err).emit("<repl>", &line),
+        }
     }
 }
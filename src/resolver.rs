@@ -0,0 +1,419 @@
+use std::{collections::HashSet, rc::Rc, slice};
+
+use crate::{
+    error::{Error, Location, Result},
+    eval::Env,
+    lexer::Span,
+    parser::{Op, Type, AST},
+};
+
+/// Binds the identifiers of a parsed program to where they are declared
+/// before it runs. Variables of functions and `for` loops become
+/// [`AST::Local`]s, found by slot instead of by name, and identifiers declared
+/// nowhere are reported without running anything.
+///
+/// A use sees the declarations above it in its own function. One above the
+/// `let` of a name means whatever the name does around that scope, and only
+/// when nothing around declares it is it bound to the slot, for a function to
+/// call one declared after it or a loop to read what its last turn set.
+pub struct Resolver<'a> {
+    /// The globals declared before the program, like by earlier lines of a
+    /// REPL, and the builtins.
+    env: &'a Env,
+    /// The names the program declares at the top level.
+    globals: HashSet<Rc<str>>,
+    /// The function and loop scopes around the node being resolved,
+    /// innermost last.
+    scopes: Vec<Scope>,
+}
+
+struct Scope {
+    /// Every name declared in the scope, at its slot.
+    slots: Vec<Rc<str>>,
+    /// Which of them are declared above the node being resolved.
+    declared: Vec<bool>,
+    /// Scopes around a function are only looked at once it's called.
+    function: bool,
+    /// The names declared in the `while` loops open in the scope, which a
+    /// later turn of the loop sees before reaching their `let`.
+    looping: Vec<Rc<str>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(env: &'a Env) -> Self {
+        return Self {
+            env,
+            globals: HashSet::new(),
+            scopes: Vec::new(),
+        };
+    }
+
+    /// Resolves a program, failing on the first identifier declared nowhere.
+    /// Resolving it again gives the same tree.
+    pub fn resolve(&mut self, program: Vec<AST>) -> Result<Vec<AST>> {
+        let mut globals = Vec::new();
+        hoist(&program, &mut globals);
+        self.globals.extend(globals);
+
+        return program
+            .into_iter()
+            .map(|ast| self.resolve_ast(ast))
+            .collect();
+    }
+
+    fn resolve_ast(&mut self, tree: AST) -> Result<AST> {
+        let resolved = match tree {
+            AST::Type(Type::Ident(name), span) | AST::Local { name, span, .. } => {
+                self.resolve_name(name, span)?
+            }
+            AST::Type(Type::Arr(items), span) => {
+                AST::Type(Type::Arr(Box::new(self.resolve_all(*items)?)), span)
+            }
+            AST::Type(Type::Template(parts), span) => {
+                AST::Type(Type::Template(Box::new(self.resolve_all(*parts)?)), span)
+            }
+            AST::Type(Type::Map(pairs), span) => {
+                let mut resolved = Vec::new();
+                for (key, value) in pairs.into_iter() {
+                    resolved.push((self.resolve_ast(key)?, self.resolve_ast(value)?));
+                }
+                AST::Type(Type::Map(Box::new(resolved)), span)
+            }
+            AST::Type(value, span) => AST::Type(value, span),
+
+            // the name after `.` is a field, not a variable
+            AST::Expr(Op::Dot, mut operands, span) => {
+                if let Some(receiver) = operands.first_mut() {
+                    *receiver = self.resolve_ast(receiver.clone())?;
+                }
+                AST::Expr(Op::Dot, operands, span)
+            }
+            AST::Expr(op, operands, span) => AST::Expr(op, self.resolve_all(operands)?, span),
+
+            AST::Let {
+                ident,
+                value,
+                doc,
+                span,
+                ..
+            } => {
+                // the value is resolved first, `let x = x + 1;` reads the outer `x`
+                let value = self.resolve_ast(*value)?;
+                let slot = self.declare(&ident);
+                AST::Let {
+                    ident,
+                    value: Box::new(value),
+                    doc,
+                    slot,
+                    span,
+                }
+            }
+            AST::Fn {
+                name,
+                params,
+                body,
+                doc,
+                span,
+                ..
+            } => {
+                let slot = name.as_ref().and_then(|name| self.declare(name));
+                let body = self.resolve_scope(params.to_vec(), true, &body)?;
+                AST::Fn {
+                    name,
+                    params,
+                    body,
+                    doc,
+                    slot,
+                    span,
+                }
+            }
+            AST::Call { calle, args, span } => AST::Call {
+                calle: Box::new(self.resolve_ast(*calle)?),
+                args: self.resolve_all(args.to_vec())?.into(),
+                span,
+            },
+            AST::Return { value, span } => AST::Return {
+                value: Box::new(self.resolve_ast(*value)?),
+                span,
+            },
+
+            AST::If {
+                condition,
+                yes,
+                no,
+                span,
+            } => AST::If {
+                condition: Box::new(self.resolve_ast(*condition)?),
+                yes: self.resolve_all(yes.to_vec())?.into(),
+                no: match no {
+                    Some(no) => Some(self.resolve_all(no.to_vec())?.into()),
+                    None => None,
+                },
+                span,
+            },
+            AST::While {
+                condition,
+                body,
+                span,
+            } => {
+                let condition = self.resolve_ast(*condition)?;
+                let open = self.scopes.last().map(|scope| scope.looping.len());
+                if let Some(scope) = self.scopes.last_mut() {
+                    hoist(&body, &mut scope.looping);
+                }
+                let body = self.resolve_all(body.to_vec());
+                if let (Some(scope), Some(open)) = (self.scopes.last_mut(), open) {
+                    scope.looping.truncate(open);
+                }
+
+                AST::While {
+                    condition: Box::new(condition),
+                    body: body?.into(),
+                    span,
+                }
+            }
+            AST::For {
+                index,
+                item,
+                iterable,
+                body,
+                span,
+            } => {
+                let iterable = self.resolve_ast(*iterable)?;
+                let slots = index.iter().chain([&item]).cloned().collect();
+                AST::For {
+                    body: self.resolve_scope(slots, false, &body)?,
+                    index,
+                    item,
+                    iterable: Box::new(iterable),
+                    span,
+                }
+            }
+            AST::Break(span) => AST::Break(span),
+            AST::Continue(span) => AST::Continue(span),
+        };
+
+        return Ok(resolved);
+    }
+
+    fn resolve_all(&mut self, trees: Vec<AST>) -> Result<Vec<AST>> {
+        return trees
+            .into_iter()
+            .map(|tree| self.resolve_ast(tree))
+            .collect();
+    }
+
+    /// Resolves the body of a function or loop in a scope of its own, where
+    /// `slots` starts with its params or loop variables.
+    fn resolve_scope(
+        &mut self,
+        mut slots: Vec<Rc<str>>,
+        function: bool,
+        body: &[AST],
+    ) -> Result<Rc<[AST]>> {
+        let mut declared = vec![true; slots.len()];
+        hoist(body, &mut slots);
+        declared.resize(slots.len(), false);
+
+        self.scopes.push(Scope {
+            slots,
+            declared,
+            function,
+            looping: Vec::new(),
+        });
+        let body = self.resolve_all(body.to_vec());
+        self.scopes.pop();
+
+        return Ok(body?.into());
+    }
+
+    /// Marks `name` as declared from here on in the innermost scope, and
+    /// returns its slot. Top level declarations are globals and have none.
+    fn declare(&mut self, name: &Rc<str>) -> Option<usize> {
+        let scope = self.scopes.last_mut()?;
+        let slot = match scope.slots.iter().rposition(|slot| slot == name) {
+            Some(slot) => slot,
+            None => {
+                scope.slots.push(name.clone());
+                scope.declared.push(false);
+                scope.slots.len() - 1
+            }
+        };
+        scope.declared[slot] = true;
+
+        return Some(slot);
+    }
+
+    /// Finds what a name refers to, failing when it's declared nowhere the
+    /// use can see.
+    fn resolve_name(&self, name: Rc<str>, span: Span) -> Result<AST> {
+        return self
+            .lookup(&name, span)
+            .ok_or_else(|| Error::reference(name).with_location(Location::Span(span)));
+    }
+
+    /// Looks `name` up in the scopes, innermost first, then in the globals.
+    /// A slot whose `let` is further down is only the last resort.
+    fn lookup(&self, name: &Rc<str>, span: Span) -> Option<AST> {
+        let mut called_later = false;
+        let mut later = None;
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
+            if let Some(slot) = scope.slots.iter().rposition(|slot| slot == name) {
+                let local = AST::Local {
+                    name: name.clone(),
+                    depth: self.scopes.len() - 1 - index,
+                    slot,
+                    span,
+                };
+                if scope.declared[slot] {
+                    return Some(local);
+                }
+                if later.is_none() && (called_later || scope.looping.contains(name)) {
+                    later = Some(local);
+                }
+            }
+            called_later |= scope.function;
+        }
+
+        if self.globals.contains(name) || self.env.get(name).is_ok() {
+            return Some(AST::Type(Type::Ident(name.clone()), span));
+        }
+
+        return later;
+    }
+}
+
+/// Collects the names declared in a scope, without the ones of the
+/// functions and loops in it, which have scopes of their own.
+fn hoist(trees: &[AST], names: &mut Vec<Rc<str>>) {
+    for tree in trees {
+        match tree {
+            AST::Let { ident, value, .. } => {
+                hoist(slice::from_ref(value.as_ref()), names);
+                if !names.contains(ident) {
+                    names.push(ident.clone());
+                }
+            }
+            AST::Fn { name, .. } => {
+                if let Some(name) = name.as_ref().filter(|name| !names.contains(name)) {
+                    names.push(name.clone());
+                }
+            }
+            AST::For { iterable, .. } => hoist(slice::from_ref(iterable.as_ref()), names),
+            AST::If {
+                condition, yes, no, ..
+            } => {
+                hoist(slice::from_ref(condition.as_ref()), names);
+                hoist(yes, names);
+                if let Some(no) = no {
+                    hoist(no, names);
+                }
+            }
+            AST::While {
+                condition, body, ..
+            } => {
+                hoist(slice::from_ref(condition.as_ref()), names);
+                hoist(body, names);
+            }
+            AST::Call { calle, args, .. } => {
+                hoist(slice::from_ref(calle.as_ref()), names);
+                hoist(args, names);
+            }
+            AST::Return { value, .. } => hoist(slice::from_ref(value.as_ref()), names),
+            AST::Expr(_, operands, _) => hoist(operands, names),
+            AST::Type(Type::Arr(items), _) | AST::Type(Type::Template(items), _) => {
+                hoist(items, names)
+            }
+            AST::Type(Type::Map(pairs), _) => {
+                for (key, value) in pairs.iter() {
+                    hoist(slice::from_ref(key), names);
+                    hoist(slice::from_ref(value), names);
+                }
+            }
+            AST::Type(..) | AST::Local { .. } | AST::Break(_) | AST::Continue(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::{
+        error::{Error, Location, Result},
+        eval::{Env, Evaluator},
+        lexer::Span,
+        parser::{Parser, Type, AST},
+    };
+
+    /// The variables in an expression, locals as `name@depth.slot`.
+    fn variables(tree: &AST, out: &mut Vec<String>) {
+        match tree {
+            AST::Local {
+                name, depth, slot, ..
+            } => out.push(format!("{name}@{depth}.{slot}")),
+            AST::Type(Type::Ident(name), _) => out.push(name.to_string()),
+            AST::Expr(_, operands, _) => operands.iter().for_each(|tree| variables(tree, out)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn slots() -> Result<()> {
+        let code =
+            "let c = 1; fn f(a) { let b = a; for x in [b] { return fn() { a + b + c + x }; } }";
+        let program = Parser::new(code.into())
+            .parse()
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        let resolved = Resolver::new(&Env::new()).resolve(program)?;
+
+        let AST::Fn { body, slot, .. } = &resolved[1] else {
+            panic!("expected a function, got {}", resolved[1]);
+        };
+        assert_eq!(None, *slot);
+        let AST::Let { slot, .. } = &body[0] else {
+            panic!("expected a let, got {}", body[0]);
+        };
+        assert_eq!(Some(1), *slot);
+
+        let AST::For { body, .. } = &body[1] else {
+            panic!("expected a for loop, got {}", body[1]);
+        };
+        let AST::Return { value, .. } = &body[0] else {
+            panic!("expected a return, got {}", body[0]);
+        };
+        let AST::Fn { body, .. } = value.as_ref() else {
+            panic!("expected a function, got {value}");
+        };
+
+        let mut found = Vec::new();
+        variables(&body[0], &mut found);
+        assert_eq!(vec!["a@2.0", "b@2.1", "c", "x@1.0"], found);
+
+        // resolving again changes nothing
+        assert_eq!(
+            resolved,
+            Resolver::new(&Env::new()).resolve(resolved.clone())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn undeclared_identifiers() {
+        let code = "print(\"ran\");\nfn f() { nope }";
+        let mut printed = Vec::new();
+        let mut evaluator = Evaluator::new(Env::new(), &mut printed);
+        let err = evaluator.eval(Parser::new(code.into()).parse());
+
+        let location = Location::Span(Span::new(23, 4, 2, 10));
+        assert_eq!(Err(Error::reference("nope").with_location(location)), err);
+
+        // nothing ran, and globals from before count as declared
+        evaluator
+            .eval(Parser::new("let nope = 1;".into()).parse())
+            .unwrap();
+        assert!(evaluator.eval(Parser::new(code.into()).parse()).is_ok());
+        assert_eq!("ran\n", String::from_utf8(printed).unwrap());
+    }
+}
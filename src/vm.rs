@@ -0,0 +1,648 @@
+use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
+
+use crate::{
+    compiler::{binary_op, Compiler, Constant, Function, OpCode, ASSIGN_OPS},
+    error::{Error, Frame, Location, Result},
+    eval::{
+        methods::{array_method, eval_member, expect_args, map_method, no_method, string_method},
+        ops::{
+            assign_index, check_loop_signal, eval_comparison, eval_compound, eval_equality,
+            eval_index, eval_infix_numbers, eval_plus, eval_prefix, eval_range, is_truth, iterate,
+        },
+        Env, FromValue, HashKey, IntoArgs, NativeFn, Scope, Value,
+    },
+    lexer::Span,
+    parser::{Op, AST},
+    resolver::Resolver,
+};
+
+/// How deep script calls can nest before the VM gives up on the program.
+const MAX_FRAMES: usize = 10_000;
+
+/// Runs programs compiled to bytecode, behaving like the tree walking
+/// [`Evaluator`](crate::eval::Evaluator). Script functions calling each other
+/// push frames instead of recursing in Rust.
+pub struct Vm<W: Write> {
+    /// The globals.
+    pub env: Env,
+    /// The function call or `for` iteration being run, none at the top level.
+    scope: Option<Rc<Scope>>,
+    stdout: W,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    /// The `for` loops being run, innermost last.
+    loops: Vec<ForLoop>,
+}
+
+struct CallFrame {
+    function: Rc<Function>,
+    ip: usize,
+    /// Where the instruction being run starts, its errors point to its span.
+    op_start: usize,
+    /// The height of the stack when called, what's above belongs to the call.
+    base: usize,
+    /// The scope to go back to on return.
+    caller_scope: Option<Rc<Scope>>,
+    /// The loops that were running when called.
+    loops: usize,
+    /// The call as shown in backtraces, the program itself has none.
+    trace: Option<Frame>,
+}
+
+struct ForLoop {
+    items: Box<dyn Iterator<Item = (Value, Value)>>,
+    /// Maps bind their keys to the item when there's no index.
+    keyed: bool,
+}
+
+enum Step {
+    Next,
+    /// The frame being waited for returned this value.
+    Done(Value),
+}
+
+impl<W: Write> Vm<W> {
+    pub fn new(env: Env, stdout: W) -> Self {
+        return Self {
+            env,
+            scope: None,
+            stdout,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            loops: Vec::new(),
+        };
+    }
+
+    /// Exposes a Rust function to scripts as `name`, like
+    /// [`Evaluator::register_fn`](crate::eval::Evaluator::register_fn).
+    pub fn register_fn<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + 'static,
+    {
+        self.env
+            .set(name.into(), Value::Native(NativeFn::new(name, func)));
+    }
+
+    /// Calls the script function bound to `name`, e.g. one defined by a
+    /// program loaded earlier with `eval`, and converts its result.
+    pub fn call_function<T: FromValue>(&mut self, name: &str, args: impl IntoArgs) -> Result<T> {
+        let function = self.env.get(&name.into())?;
+        let result = self.call_now(function, args.into_args()?);
+        if result.is_err() {
+            self.reset();
+        }
+
+        return T::from_value(result?);
+    }
+
+    /// Compiles and runs a parsed program, with the same results as
+    /// [`Evaluator::eval`](crate::eval::Evaluator::eval).
+    pub fn eval(&mut self, statements: Vec<Result<AST>>) -> Result<Value> {
+        let statements = statements.into_iter().collect::<Result<Vec<AST>>>()?;
+        let statements = Resolver::new(&self.env).resolve(statements)?;
+        let program = Rc::new(Compiler::compile(&statements)?);
+
+        self.frames.push(CallFrame {
+            function: program,
+            ip: 0,
+            op_start: 0,
+            base: 0,
+            caller_scope: None,
+            loops: 0,
+            trace: None,
+        });
+
+        let result = self.run(0);
+        if result.is_err() {
+            self.reset();
+        }
+
+        return result;
+    }
+
+    /// Drops what a failed run left behind, the globals it changed stay.
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.loops.clear();
+        self.scope = None;
+    }
+
+    /// Runs until only `stop` frames are left, and returns what the last
+    /// one to go returned.
+    fn run(&mut self, stop: usize) -> Result<Value> {
+        loop {
+            match self.step(stop) {
+                Ok(Step::Next) => continue,
+                Ok(Step::Done(value)) => return Ok(value),
+                Err(err) => return Err(self.locate(err)),
+            }
+        }
+    }
+
+    /// Points errors that don't know where they happened yet at the
+    /// instruction being run, and gives them the calls that led to it.
+    fn locate(&self, err: Error) -> Error {
+        let Some(frame) = self.frames.last() else {
+            return err;
+        };
+
+        let err = match err.location() {
+            Location::Unknown => {
+                err.with_location(Location::Span(frame.function.chunk.spans[frame.op_start]))
+            }
+            _ => err,
+        };
+        if !err.backtrace().is_empty() {
+            return err;
+        }
+
+        let frames = self
+            .frames
+            .iter()
+            .rev()
+            .filter_map(|frame| frame.trace.clone())
+            .collect();
+        return err.with_backtrace(frames);
+    }
+
+    fn step(&mut self, stop: usize) -> Result<Step> {
+        let frame = self.frames.last_mut().expect("a frame to run");
+        let function = frame.function.clone();
+        let chunk = &function.chunk;
+
+        frame.op_start = frame.ip;
+        let op = OpCode::from_byte(chunk.code[frame.ip]).expect("a valid opcode");
+        let mut operands = [0; 3];
+        for (i, operand) in operands.iter_mut().take(op.operands()).enumerate() {
+            *operand = chunk.read_u16(frame.ip + 1 + 2 * i);
+        }
+        frame.ip += 1 + 2 * op.operands();
+
+        let [first, second, third] = operands;
+        let name = |index: u16| match &chunk.constants[index as usize] {
+            Constant::String(name) => name.clone(),
+            constant => unreachable!("names are strings, got {constant}"),
+        };
+
+        match op {
+            OpCode::Constant => {
+                let value = match &chunk.constants[first as usize] {
+                    Constant::Int(num) => Value::Int(*num),
+                    Constant::Float(num) => Value::Float(*num),
+                    Constant::String(str) => Value::String(str.clone()),
+                    Constant::Function(_) => unreachable!("functions are loaded as closures"),
+                };
+                self.stack.push(value);
+            }
+            OpCode::Nil => self.stack.push(Value::Nil),
+            OpCode::True => self.stack.push(Value::Bool(true)),
+            OpCode::False => self.stack.push(Value::Bool(false)),
+            OpCode::Idle => self.stack.push(Value::Idle),
+            OpCode::Pop => {
+                self.pop();
+            }
+            OpCode::PopN => self.stack.truncate(self.stack.len() - first as usize),
+
+            OpCode::GetName => {
+                let value = self.env.get(&name(first))?;
+                self.stack.push(value);
+            }
+            OpCode::DefineName => {
+                let ident = name(first);
+                let mut value = self.pop();
+                // anonymous functions go by the first name they're bound to
+                if let Value::Closure { name, .. } = &mut value {
+                    if name.is_none() {
+                        *name = Some(ident.clone());
+                    }
+                }
+                self.env.set(ident, value);
+            }
+            OpCode::SetName => {
+                let value = self.pop();
+                self.env.assign(name(first), value)?;
+                self.stack.push(Value::Idle);
+            }
+            OpCode::GetLocal => {
+                let value =
+                    self.local_scope()
+                        .get(second as usize, third as usize, &name(first))?;
+                self.stack.push(value);
+            }
+            OpCode::DefineLocal => {
+                let ident = name(first);
+                let mut value = self.pop();
+                if let Value::Closure { name, .. } = &mut value {
+                    if name.is_none() {
+                        *name = Some(ident);
+                    }
+                }
+                self.local_scope().set(second as usize, value);
+            }
+            OpCode::SetLocal => {
+                let value = self.pop();
+                let (depth, slot) = (second as usize, third as usize);
+                self.local_scope()
+                    .assign(depth, slot, &name(first), value)?;
+                self.stack.push(Value::Idle);
+            }
+            OpCode::Document => {
+                if let Some(Value::Closure { doc, .. }) = self.stack.last_mut() {
+                    if doc.is_none() {
+                        *doc = Some(name(first));
+                    }
+                }
+            }
+            OpCode::GetMember => {
+                let receiver = self.pop();
+                let value = eval_member(receiver, &name(first))?;
+                self.stack.push(value);
+            }
+            OpCode::SetIndex => {
+                let container = self.pop();
+                let key = self.pop();
+                let value = self.pop();
+                let current = eval_index(container.clone(), key.clone())?;
+                let value = eval_compound(ASSIGN_OPS[first as usize], current, value)?;
+                let stored = assign_index(container, key, value)?;
+                self.stack.push(stored);
+            }
+            OpCode::NotAssignable => {
+                let op = ASSIGN_OPS[second as usize];
+                return Err(Error::runtime(
+                    op,
+                    format!("'{}' is not assignable", name(first)),
+                ));
+            }
+
+            OpCode::Negate | OpCode::Not => {
+                let value = self.pop();
+                let op = if op == OpCode::Negate {
+                    Op::Minus
+                } else {
+                    Op::Bang
+                };
+                self.stack.push(eval_prefix(op, value)?);
+            }
+            OpCode::Array => {
+                let items = self.stack.split_off(self.stack.len() - first as usize);
+                self.stack.push(Value::Array(Rc::new(RefCell::new(items))));
+            }
+            OpCode::Map => {
+                let entries = self.stack.split_off(self.stack.len() - 2 * first as usize);
+                let mut map = HashMap::new();
+                let mut entries = entries.into_iter();
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    map.insert(HashKey::try_from(key)?, value);
+                }
+                self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+            }
+            OpCode::CheckKey => {
+                let key = self.stack.last().expect("a key to check").clone();
+                HashKey::try_from(key)?;
+            }
+            OpCode::Template => {
+                let parts = self.stack.split_off(self.stack.len() - first as usize);
+                let string: String = parts.iter().map(|part| part.to_string()).collect();
+                self.stack.push(Value::String(string.into()));
+            }
+
+            OpCode::Jump => self.jump(first),
+            OpCode::JumpIfFalse => {
+                if !is_truth(self.pop()) {
+                    self.jump(first);
+                }
+            }
+            OpCode::JumpIfFalseOrPop | OpCode::JumpIfTrueOrPop => {
+                let value = self.stack.last().expect("a condition").clone();
+                if is_truth(value) == (op == OpCode::JumpIfTrueOrPop) {
+                    self.jump(first);
+                } else {
+                    self.pop();
+                }
+            }
+
+            OpCode::IterStart => {
+                let iterable = self.pop();
+                let keyed = matches!(iterable, Value::Map(_));
+                let items = iterate(iterable)?;
+                self.loops.push(ForLoop { items, keyed });
+            }
+            OpCode::IterNext => {
+                let current = self.loops.last_mut().expect("a loop to iterate");
+                let keyed = current.keyed;
+                let Some((position, element)) = current.items.next() else {
+                    self.jump(first);
+                    return Ok(Step::Next);
+                };
+
+                // the loop variables take the first slots, index before item
+                let slots = if second == 1 {
+                    vec![position, element]
+                } else if keyed {
+                    vec![position]
+                } else {
+                    vec![element]
+                };
+                self.scope = Some(Scope::new(slots, self.scope.take()));
+            }
+            OpCode::PopScope => {
+                let scope = self.scope.take().expect("a scope to leave");
+                self.scope = scope.outer();
+            }
+            OpCode::IterEnd => {
+                self.loops.pop();
+            }
+
+            OpCode::Closure => {
+                let Constant::Function(function) = &chunk.constants[first as usize] else {
+                    unreachable!("closures are made from functions");
+                };
+                self.stack.push(Value::Closure {
+                    name: function.name.clone(),
+                    function: function.clone(),
+                    doc: function.doc.clone(),
+                    scope: self.scope.clone(),
+                });
+            }
+            OpCode::Call => {
+                let args = self.stack.split_off(self.stack.len() - first as usize);
+                let function = self.pop();
+                if let Some(value) = self.call(function, args)? {
+                    self.stack.push(value);
+                }
+            }
+            OpCode::CallMethod => {
+                let args = self.stack.split_off(self.stack.len() - second as usize);
+                let receiver = self.pop();
+                if let Some(value) = self.call_method(receiver, &name(first), args)? {
+                    self.stack.push(value);
+                }
+            }
+            OpCode::Return => {
+                let value = self.pop();
+                // the program itself returning ends it, unlike its functions
+                let program = self
+                    .frames
+                    .last()
+                    .is_some_and(|frame| frame.trace.is_none());
+                self.leave();
+                if program {
+                    return Ok(Step::Done(Value::Return(Box::new(value))));
+                }
+                if self.frames.len() == stop {
+                    return Ok(Step::Done(value));
+                }
+                self.stack.push(value);
+            }
+            OpCode::Escape => {
+                let signal = if first == 0 {
+                    Value::Break
+                } else {
+                    Value::Continue
+                };
+                // a stray signal in a function fails at the call, at the top
+                // level there's no call so it fails right here
+                if self.frames.len() > 1 {
+                    self.leave();
+                }
+                check_loop_signal(signal)?;
+            }
+            OpCode::End => {
+                let value = self.pop();
+                self.frames.pop();
+                return Ok(Step::Done(value));
+            }
+
+            op => {
+                let op = binary_op(op).expect("a binary operator");
+                let left = self.pop();
+                let right = self.pop();
+                self.stack.push(eval_binary(op, left, right)?);
+            }
+        }
+
+        return Ok(Step::Next);
+    }
+
+    fn pop(&mut self) -> Value {
+        return self.stack.pop().expect("the compiler to balance the stack");
+    }
+
+    fn jump(&mut self, target: u16) {
+        self.frames.last_mut().expect("a frame to run").ip = target as usize;
+    }
+
+    fn local_scope(&self) -> &Scope {
+        return self.scope.as_deref().expect("a scope for resolved locals");
+    }
+
+    /// The span of the instruction being run.
+    fn span(&self) -> Span {
+        let frame = self.frames.last().expect("a frame to run");
+        return frame.function.chunk.spans[frame.op_start];
+    }
+
+    /// Returns from the current frame, dropping everything it left behind.
+    fn leave(&mut self) {
+        let frame = self.frames.pop().expect("a frame to leave");
+        self.stack.truncate(frame.base);
+        self.loops.truncate(frame.loops);
+        self.scope = frame.caller_scope;
+    }
+
+    /// Calls `function` with `args`. Script functions get a frame that runs
+    /// next and `None` is returned, the rest give their result right away.
+    fn call(&mut self, function: Value, args: Vec<Value>) -> Result<Option<Value>> {
+        match function {
+            Value::Closure {
+                name,
+                function,
+                scope,
+                ..
+            } => {
+                if args.len() != function.params.len() {
+                    return Err(Error::arity(
+                        "FUNCTION CALL",
+                        function.params.len(),
+                        args.len(),
+                    ));
+                }
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(Error::runtime(
+                        "FUNCTION CALL",
+                        format!("more than {MAX_FRAMES} nested calls"),
+                    ));
+                }
+
+                // params take the first slots, in order
+                let scope = Scope::new(args, scope);
+                // calls from the host have no call site
+                let trace = Frame {
+                    name,
+                    call_site: (!self.frames.is_empty()).then(|| self.span()),
+                };
+                self.frames.push(CallFrame {
+                    function,
+                    ip: 0,
+                    op_start: 0,
+                    base: self.stack.len(),
+                    caller_scope: self.scope.replace(scope),
+                    loops: self.loops.len(),
+                    trace: Some(trace),
+                });
+                Ok(None)
+            }
+            Value::Builtin(builtin) => builtin.call(&mut self.stdout, args).map(Some),
+            Value::Native(native) => native.call(&args).map(Some),
+            _ => Err(Error::runtime(
+                "FUNCTION CALL",
+                format!("not a function '{function}'"),
+            )),
+        }
+    }
+
+    /// Calls `function` and runs it to the end, for the methods that call
+    /// back into scripts.
+    fn call_now(&mut self, function: Value, args: Vec<Value>) -> Result<Value> {
+        let stop = self.frames.len();
+        match self.call(function, args)? {
+            Some(value) => Ok(value),
+            None => self.run(stop),
+        }
+    }
+
+    /// Same dispatch as the tree walker's, see `Evaluator::call_method`.
+    fn call_method(
+        &mut self,
+        receiver: Value,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Option<Value>> {
+        match receiver {
+            Value::Map(map) => {
+                let stored = map.borrow().get(&HashKey::String(name.into())).cloned();
+                match stored {
+                    Some(function) => self.call(function, args),
+                    None => map_method(map, name, args).map(Some),
+                }
+            }
+            Value::Array(arr) if name == "map" || name == "filter" => {
+                let [function] = expect_args(name, args)?;
+                let items = arr.borrow().clone();
+                let mut result = Vec::new();
+                for item in items {
+                    let evaluated = self.call_now(function.clone(), vec![item.clone()])?;
+                    if name == "map" {
+                        result.push(evaluated);
+                    } else if is_truth(evaluated) {
+                        result.push(item);
+                    }
+                }
+                Ok(Some(Value::Array(Rc::new(RefCell::new(result)))))
+            }
+            Value::Array(arr) => array_method(arr, name, args).map(Some),
+            Value::String(str) => string_method(str, name, args).map(Some),
+            value => Err(no_method(&value, name)),
+        }
+    }
+}
+
+fn eval_binary(op: Op, left: Value, right: Value) -> Result<Value> {
+    match op {
+        Op::Plus => eval_plus(left, right),
+        Op::AssignEqual | Op::BangEqual => Ok(eval_equality(op, &left, &right)),
+        Op::Greater | Op::GreaterEqual | Op::Less | Op::LessEqual => {
+            eval_comparison(op, left, right)
+        }
+        Op::Index => eval_index(left, right),
+        Op::Range | Op::RangeInclusive => eval_range(op, left, right),
+        op => eval_infix_numbers(op, left, right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Vm, MAX_FRAMES};
+    use crate::{
+        error::{Error, Location, Result},
+        eval::{arity_error, Env, Value},
+        lexer::Span,
+        parser::Parser,
+    };
+
+    #[test]
+    fn deep_recursion() -> Result<()> {
+        let code = "fn count(n) { if n == 0 { 0 } else { 1 + count(n - 1) } }";
+        let mut vm = Vm::new(Env::new(), Vec::new());
+        vm.eval(Parser::new(code.into()).parse())?;
+
+        let result = vm.eval(Parser::new("count(5000);".into()).parse());
+        assert_eq!(Ok(Value::Int(5000)), result);
+
+        let code = format!("count({MAX_FRAMES});");
+        let err = vm.eval(Parser::new(code).parse()).unwrap_err();
+        assert!(matches!(err, Error::Runtime { .. }), "got {err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn stray_signals() {
+        let mut vm = Vm::new(Env::new(), Vec::new());
+        let err = vm.eval(Parser::new("let x = 1;\nbreak;".into()).parse());
+        let location = Location::Span(Span::new(11, 6, 2, 1));
+        assert_eq!(Some(location), err.err().map(|err| err.location()));
+
+        // in a function it's the call that's to blame
+        let err = vm.eval(Parser::new("fn f() { continue; }\nf();".into()).parse());
+        let location = Location::Span(Span::new(21, 3, 2, 1));
+        assert_eq!(Some(location), err.err().map(|err| err.location()));
+    }
+
+    #[test]
+    fn state_after_errors() -> Result<()> {
+        let mut vm = Vm::new(Env::new(), Vec::new());
+        let code = "let total = 0; for x in [1, 2, 3] { fn f() { total += x; x / 0 } f(); }";
+        assert!(vm.eval(Parser::new(code.into()).parse()).is_err());
+
+        // the scopes of the loop and the call are gone, what they changed stays
+        let code = "let y = total; y;";
+        assert_eq!(Ok(Value::Int(1)), vm.eval(Parser::new(code.into()).parse()));
+        assert!(vm.eval(Parser::new("x;".into()).parse()).is_err());
+        assert!(vm.stack.is_empty() && vm.frames.is_empty() && vm.loops.is_empty());
+        assert!(vm.scope.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn host_functions() -> Result<()> {
+        let mut vm = Vm::new(Env::new(), Vec::new());
+        vm.register_fn("add", |args| match args {
+            [Value::Int(l), Value::Int(r)] => Ok(Value::Int(l + r)),
+            _ => Err(arity_error("add", 2, args.len())),
+        });
+        let code = "fn scale(xs, by) { xs.map(fn(x) { add(x, 0) * by }) } fn f() { 1 / 0 }";
+        vm.eval(Parser::new(code.into()).parse())?;
+
+        let scaled: Vec<i64> = vm.call_function("scale", (vec![1, 2], 3))?;
+        assert_eq!(vec![3, 6], scaled);
+        assert_eq!(5, vm.call_function::<i64>("add", vec![2, 3])?);
+
+        // calls from the host have no call site
+        let err = vm.call_function::<Value>("f", ()).unwrap_err();
+        assert_eq!(
+            "[line: 1, column: 64] Dividing by zero error: 1 '/' 0\n    in fn 'f' called by the host",
+            err.to_string()
+        );
+        assert!(vm.stack.is_empty() && vm.frames.is_empty() && vm.scope.is_none());
+        assert_eq!(
+            Error::reference("missing"),
+            vm.call_function::<i64>("missing", ()).unwrap_err()
+        );
+
+        Ok(())
+    }
+}
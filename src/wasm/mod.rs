@@ -4,10 +4,14 @@ use wasm_bindgen::prelude::*;
 use web_sys::HtmlElement;
 
 use crate::{
-    eval::{Env, Evaluator},
+    diagnostic::Diagnostic,
+    eval::{Env, Evaluator, Value},
     parser::Parser,
 };
 
+/// The file diagnostics name, code run in the browser has none.
+const SOURCE_NAME: &str = "playground";
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
@@ -21,13 +25,14 @@ struct ConsoleStdErr {
 impl Write for ConsoleStdErr {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         if let Ok(new_value) = String::from_utf8(buf.to_vec()) {
-            let trimmed_value = new_value.trim();
+            // diagnostics are laid out in lines and quote the source
+            let trimmed_value = escape_html(new_value.trim_end());
             let prev_values = self.console.inner_html();
 
             let new_html = if prev_values.is_empty() {
-                format!("<span>{trimmed_value}</span>")
+                format!("<span style=\"white-space: pre\">{trimmed_value}</span>")
             } else {
-                format!("{prev_values}<span>{trimmed_value}</span>")
+                format!("{prev_values}<span style=\"white-space: pre\">{trimmed_value}</span>")
             };
 
             self.console.set_inner_html(&new_html);
@@ -42,6 +47,13 @@ impl Write for ConsoleStdErr {
     }
 }
 
+fn escape_html(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+}
+
 struct ConsoleStdOut {
     console: HtmlElement,
     buffer: String,
@@ -95,11 +107,31 @@ impl Write for ConsoleStdOut {
 #[wasm_bindgen]
 pub fn eval_code(code: String, console: HtmlElement) -> String {
     let stdout = ConsoleStdOut::new(console.clone());
-    let stderr = ConsoleStdErr { console };
+    let mut stderr = ConsoleStdErr { console };
+
+    let ast = Parser::new(code.clone()).parse();
+    // every syntax error in the code, `eval` would stop at the first
+    let errors: Vec<_> = ast
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .collect();
+    if !errors.is_empty() {
+        for err in errors {
+            let rendered = Diagnostic::from(err).render(SOURCE_NAME, &code, false);
+            stderr.write_all(rendered.as_bytes()).unwrap();
+        }
+        return format!("{}", Value::Idle);
+    }
 
-    let ast = Parser::new(code).parse();
-    let mut evaluator = Evaluator::new(Env::new(), stdout, stderr);
-    let output = evaluator.eval(ast);
+    let mut evaluator = Evaluator::new(Env::new(), stdout);
+    let output = match evaluator.eval(ast) {
+        Ok(value) => value,
+        Err(err) => {
+            let rendered = Diagnostic::from(&err).render(SOURCE_NAME, &code, false);
+            stderr.write_all(rendered.as_bytes()).unwrap();
+            Value::Idle
+        }
+    };
 
     return format!("{output}");
 }
@@ -2,7 +2,7 @@ import { EditorView, basicSetup } from "codemirror";
 import { EditorState } from "@codemirror/state";
 
 let startState = EditorState.create({
-	doc: `print "Hello world";`,
+	doc: `print("Hello world");`,
 	extensions: [basicSetup]
 });
 
@@ -2,7 +2,7 @@ import { replaceText } from "./editor";
 
 const data = {
 	arr: [
-		["Hello world", `print "Hello world";`],
+		["Hello world", `print("Hello world");`],
 		["Computing int", `(10 + 2) * 30 + 5`],
 		[
 			"Variable", `let x = 10;
@@ -12,28 +12,42 @@ x + y;` ],
 
 		[
 			"Conditionals", `if true {
-  print "Hello";
+  print("Hello");
 } else {
-  print "unreachable";
+  print("unreachable");
 }`,
 		],
 
 		[
 			"Array", `let arr = ["one", "two", "three"];
-print arr[0];
-print arr[1];
-print arr[2];
+print(arr[0]);
+print(arr[1]);
+print(arr[2]);
 
-print "---- >8 ----";
+print("---- >8 ----");
 
-print len(arr);
-print first(arr);
-print last(arr);
+print(len(arr));
+print(first(arr));
+print(last(arr));
 
-print "---- >8 ----";
+print("---- >8 ----");
 
 let arr = push(arr, "four");
-print last(arr);`],
+print(last(arr));`],
+
+		[
+			"While loop", `let i = 0;
+let sum = 0;
+
+while i < 10 {
+  i = i + 1;
+  if i == 5 {
+    continue;
+  }
+  sum = sum + i;
+}
+
+sum;`],
 
 		[
 			"function", `let factorial = fn(n) {